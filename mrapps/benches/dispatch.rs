@@ -0,0 +1,85 @@
+// Compares the shared job queue with work-stealing dispatch on many tiny
+// tasks. Whole jobs also spend time writing task outputs and the
+// write-ahead log, so this drives the queues directly. Run with `cargo bench -p mrapps`.
+
+use std::{
+    hint::black_box,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use mrapps::{dispatch::Deques, worker::Job};
+
+const N_TASKS: i32 = 200_000;
+const N_WORKERS: usize = 8;
+const RUNS: usize = 7;
+
+// A tiny task, standing in for a small split.
+fn work(job: Job) -> usize {
+    match job {
+        Job::Map((id, path)) | Job::MapOnly((id, path)) => {
+            black_box(path.as_os_str().len() + id as usize)
+        }
+        Job::Reduce((id, paths)) => black_box(paths.len() + id as usize),
+    }
+}
+
+fn jobs() -> impl Iterator<Item = Job> {
+    (0..N_TASKS).map(|i| Job::Map((i, PathBuf::from(format!("input_{}", i % 64)))))
+}
+
+fn shared_queue() -> Duration {
+    let start = Instant::now();
+    let (job_queue, worker_job_queue) = chan::r#async();
+    let workers = (0..N_WORKERS)
+        .map(|_| {
+            let job_queue = worker_job_queue.clone();
+            thread::spawn(move || job_queue.iter().map(work).sum::<usize>())
+        })
+        .collect::<Vec<_>>();
+    jobs().for_each(|job| job_queue.send(job));
+    drop(job_queue);
+    workers.into_iter().for_each(|w| drop(w.join()));
+    start.elapsed()
+}
+
+fn work_stealing() -> Duration {
+    let start = Instant::now();
+    let deques = Arc::new(Deques::new(N_WORKERS));
+    let workers = (0..N_WORKERS)
+        .map(|id| {
+            let deques = deques.clone();
+            thread::spawn(move || {
+                let mut total = 0;
+                while let Some(job) = deques.pop(id) {
+                    total += work(job);
+                }
+                total
+            })
+        })
+        .collect::<Vec<_>>();
+    jobs().for_each(|job| deques.push(job));
+    deques.close();
+    workers.into_iter().for_each(|w| drop(w.join()));
+    start.elapsed()
+}
+
+fn main() {
+    for (name, run) in [
+        ("shared queue", shared_queue as fn() -> Duration),
+        ("work stealing", work_stealing),
+    ] {
+        let mut times = (0..RUNS).map(|_| run()).collect::<Vec<Duration>>();
+        times.sort();
+        println!(
+            "{:<14} {} tasks on {} workers: median {:?}, best {:?}",
+            name,
+            N_TASKS,
+            N_WORKERS,
+            times[RUNS / 2],
+            times[0]
+        );
+    }
+}
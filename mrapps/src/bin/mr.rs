@@ -0,0 +1,439 @@
+// Runs and debugs jobs from the command line:
+//
+//     mr run --app wc --workers 8 --reduce 10 inputs/*
+//     mr plan --sample 2 --app wc --reduce 10 inputs/*
+//     mr status <workdir>
+//     mr inspect <intermediate-file>
+//     mr trace <events-file> > trace.json
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+};
+
+use mrapps::{
+    checkpoint::{read_log, Entry},
+    master::Master,
+    output::{json_line, partition_index, OutputFormat},
+    spec::{JobSpec, SpecError, DEFAULT_N_REDUCE},
+    status::{TaskId, TaskState},
+    trace::{chrome_trace, read_events, JsonLinesSink},
+};
+
+const USAGE: &str = "usage:
+    mr run --app <wc|terasort> [--workers N] [--reduce N] [--workdir DIR]
+           [--output <text|json>] [--merge] INPUT...
+    mr run --spec FILE [--workers N]
+    mr plan [--sample N] <run options>
+    mr status WORKDIR
+    mr inspect FILE
+    mr trace EVENTS
+
+    plan explains a job without running it, mapping N inputs (default 0)
+    to estimate its intermediate volume. run --trace EVENTS writes task
+    events as JSON lines; trace turns them into a Chrome trace.";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(RunArgs),
+    Plan { sample: usize, run: RunArgs },
+    Status(PathBuf),
+    Inspect(PathBuf),
+    Trace(PathBuf),
+}
+
+#[derive(Debug, PartialEq)]
+struct RunArgs {
+    workers: i32,
+    trace: Option<PathBuf>,
+    spec: Option<PathBuf>,
+    app: String,
+    n_reduce: usize,
+    working_directory: PathBuf,
+    output_format: OutputFormat,
+    merge_output: bool,
+    input_files: Vec<PathBuf>,
+}
+
+fn parse(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = args.split_first().ok_or("missing command")?;
+    match command.as_str() {
+        "run" => parse_run(rest).map(Command::Run),
+        "plan" => {
+            // --sample may come anywhere; the rest are run options.
+            let mut sample = 0;
+            let mut run_args = vec![];
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                if arg != "--sample" {
+                    run_args.push(arg.clone());
+                    continue;
+                }
+                let value = rest.next().ok_or("--sample needs a value")?;
+                sample = value
+                    .parse()
+                    .map_err(|_| format!("--sample must be a number, got `{}`", value))?;
+            }
+            Ok(Command::Plan {
+                sample,
+                run: parse_run(&run_args)?,
+            })
+        }
+        "status" | "inspect" | "trace" => {
+            let [path] = rest else {
+                return Err(format!("{} takes exactly one path", command));
+            };
+            let path = PathBuf::from(path);
+            Ok(match command.as_str() {
+                "status" => Command::Status(path),
+                "inspect" => Command::Inspect(path),
+                _ => Command::Trace(path),
+            })
+        }
+        _ => Err(format!("unknown command `{}`", command)),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<RunArgs, String> {
+    let mut run = RunArgs {
+        workers: 4,
+        trace: None,
+        spec: None,
+        app: String::new(),
+        n_reduce: DEFAULT_N_REDUCE,
+        working_directory: PathBuf::from("."),
+        output_format: OutputFormat::Text,
+        merge_output: false,
+        input_files: vec![],
+    };
+    let mut job_options = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            run.input_files.push(PathBuf::from(arg));
+            job_options = true;
+            continue;
+        }
+        if arg == "--merge" {
+            run.merge_output = true;
+            job_options = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = |value: &str| {
+            value
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("{} must be a positive number, got `{}`", arg, value))
+        };
+        job_options |= !["--workers", "--trace", "--spec"].contains(&arg.as_str());
+        match arg.as_str() {
+            "--workers" => run.workers = number(value)? as i32,
+            "--trace" => run.trace = Some(PathBuf::from(value)),
+            "--spec" => run.spec = Some(PathBuf::from(value)),
+            "--app" => run.app = value.clone(),
+            "--reduce" => run.n_reduce = number(value)?,
+            "--workdir" => run.working_directory = PathBuf::from(value),
+            "--output" => {
+                run.output_format = match value.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::JsonLines,
+                    _ => return Err(format!("unknown output format `{}`", value)),
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if run.spec.is_some() {
+        if job_options {
+            return Err("--spec only combines with --workers and --trace".to_string());
+        }
+        return Ok(run);
+    }
+    if run.app.is_empty() {
+        return Err("--app or --spec is required".to_string());
+    }
+    if run.input_files.is_empty() {
+        return Err("no input files".to_string());
+    }
+    Ok(run)
+}
+
+fn job_spec(run: RunArgs) -> Result<JobSpec, SpecError> {
+    if let Some(path) = run.spec {
+        return JobSpec::from_file(&path);
+    }
+    Ok(JobSpec {
+        n_reduce: run.n_reduce,
+        working_directory: run.working_directory,
+        output_format: run.output_format,
+        merge_output: run.merge_output,
+        ..JobSpec::new(&run.app, run.input_files)?
+    })
+}
+
+fn write_error(error: io::Error) -> String {
+    format!("writing output: {}", error)
+}
+
+// Returns whether every task succeeded.
+fn run(run: RunArgs, out: &mut dyn Write) -> Result<bool, String> {
+    let workers = run.workers;
+    let trace = run.trace.clone();
+    let spec = job_spec(run).map_err(|e| e.to_string())?;
+    let mut master = Master::from_spec(&spec).map_err(|e| e.to_string())?;
+    if let Some(path) = trace {
+        let sink =
+            JsonLinesSink::create(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        master.set_trace_sink(Arc::new(sink));
+    }
+    let mut result_files = master.run(workers);
+
+    let snapshot = master.status().snapshot();
+    let failed = snapshot
+        .tasks
+        .iter()
+        .filter(|task| task.state == TaskState::Failed)
+        .collect::<Vec<_>>();
+    for task in failed.iter() {
+        let error = task.error.as_deref().unwrap_or("unknown error");
+        eprintln!("{} {} failed: {}", task.id.kind(), task.id.index(), error);
+    }
+    if let Some(error) = &snapshot.error {
+        eprintln!("job failed: {}", error);
+    }
+    if !failed.is_empty() || snapshot.error.is_some() {
+        return Ok(false);
+    }
+
+    result_files.sort_by_key(|path| partition_index(path));
+    for path in result_files {
+        writeln!(out, "{}", path.display()).map_err(write_error)?;
+    }
+    Ok(true)
+}
+
+fn plan(sample: usize, run: RunArgs, out: &mut dyn Write) -> Result<bool, String> {
+    let spec = job_spec(run).map_err(|e| e.to_string())?;
+    let master = Master::from_spec(&spec).map_err(|e| e.to_string())?;
+    let plan = master.plan(sample).map_err(|e| e.to_string())?;
+    write!(out, "{}", plan).map_err(write_error)?;
+    Ok(true)
+}
+
+// Prints the last recorded state of every task in the job's log.
+fn status(working_directory: &Path, out: &mut dyn Write) -> Result<bool, String> {
+    let entries = read_log(working_directory)
+        .map_err(|e| format!("{}: {}", working_directory.display(), e))?;
+
+    let mut inputs = 0;
+    let mut tasks = BTreeMap::<TaskId, TaskState>::new();
+    for entry in entries {
+        match entry {
+            Entry::Input(_) => inputs += 1,
+            Entry::Dispatched(id) => {
+                tasks.insert(id, TaskState::InProgress);
+            }
+            Entry::Done(id, _) => {
+                tasks.insert(id, TaskState::Done);
+            }
+            Entry::Failed(id) => {
+                tasks.insert(id, TaskState::Failed);
+            }
+        }
+    }
+
+    writeln!(out, "inputs: {}", inputs).map_err(write_error)?;
+    for (id, state) in tasks.iter() {
+        writeln!(out, "{} {}: {}", id.kind(), id.index(), state).map_err(write_error)?;
+    }
+    let count = |state| tasks.values().filter(|s| **s == state).count();
+    writeln!(
+        out,
+        "done: {}, in-progress: {}, failed: {}",
+        count(TaskState::Done),
+        count(TaskState::InProgress),
+        count(TaskState::Failed)
+    )
+    .map_err(write_error)?;
+    Ok(true)
+}
+
+// Prints each record of a task's output file as a JSON object.
+fn inspect(path: &Path, out: &mut dyn Write) -> Result<bool, String> {
+    let f = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        writeln!(out, "{}", json_line(&line)).map_err(write_error)?;
+    }
+    Ok(true)
+}
+
+fn trace(path: &Path, out: &mut dyn Write) -> Result<bool, String> {
+    let events = read_events(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    writeln!(out, "{}", chrome_trace(&events)).map_err(write_error)?;
+    Ok(true)
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let command = match parse(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("mr: {}\n{}", error, USAGE);
+            exit(2);
+        }
+    };
+
+    let out = &mut io::stdout().lock();
+    let outcome = match command {
+        Command::Run(args) => run(args, out),
+        Command::Plan { sample, run } => plan(sample, run, out),
+        Command::Status(working_directory) => status(&working_directory, out),
+        Command::Inspect(path) => inspect(&path, out),
+        Command::Trace(path) => trace(&path, out),
+    };
+    match outcome {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(error) => {
+            eprintln!("mr: {}", error);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mrapps::pipeline::remove_job_outputs;
+
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_run_options_and_inputs() {
+        let command = parse(&args(
+            "run --app wc --workers 8 --reduce 3 a b --merge --output json",
+        ));
+        assert_eq!(
+            command,
+            Ok(Command::Run(RunArgs {
+                workers: 8,
+                trace: None,
+                spec: None,
+                app: "wc".to_string(),
+                n_reduce: 3,
+                working_directory: PathBuf::from("."),
+                output_format: OutputFormat::JsonLines,
+                merge_output: true,
+                input_files: vec![PathBuf::from("a"), PathBuf::from("b")],
+            }))
+        );
+
+        assert_eq!(
+            parse(&args("status work")),
+            Ok(Command::Status(PathBuf::from("work")))
+        );
+        assert!(parse(&args("run --app wc")).is_err());
+        assert!(parse(&args("run --app wc --workers 0 a")).is_err());
+        assert!(parse(&args("inspect a b")).is_err());
+        assert!(parse(&args("run --spec job.toml --workers 2 --trace t")).is_ok());
+        assert!(parse(&args("run --spec job.toml --reduce 2")).is_err());
+        assert!(matches!(
+            parse(&args("plan --sample 2 --spec job.toml")),
+            Ok(Command::Plan { sample: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn plan_takes_sample_anywhere() {
+        for line in [
+            "plan --sample 2 --app wc a",
+            "plan --app wc --sample 2 a",
+            "plan --app wc a --sample 2",
+        ] {
+            match parse(&args(line)) {
+                Ok(Command::Plan { sample, run }) => {
+                    assert_eq!(sample, 2, "{}", line);
+                    assert_eq!(run.input_files, vec![PathBuf::from("a")], "{}", line);
+                }
+                command => panic!("{}: {:?}", line, command),
+            }
+        }
+        assert!(parse(&args("plan --app wc a --sample")).is_err());
+        assert!(parse(&args("plan --app wc --sample x a")).is_err());
+    }
+
+    #[test]
+    fn run_status_and_inspect_report_on_a_job() {
+        let directory = "./test-data/mr_runs_job";
+        let command = |line: &str| {
+            let mut out = vec![];
+            let ok = match parse(&args(line)).unwrap() {
+                Command::Run(args) => run(args, &mut out),
+                Command::Status(path) => status(&path, &mut out),
+                Command::Inspect(path) => inspect(&path, &mut out),
+                command => panic!("{:?}", command),
+            };
+            (ok, String::from_utf8(out).unwrap())
+        };
+
+        let (ok, listed) = command(&format!(
+            "run --app wc --workers 2 --reduce 2 --workdir {0} {0}/input_1 {0}/input_2",
+            directory
+        ));
+        assert_eq!(ok, Ok(true));
+        let result_files = listed.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            result_files,
+            [
+                format!("{}/reduce.1.result", directory),
+                format!("{}/reduce.2.result", directory)
+            ]
+        );
+
+        let (ok, state) = command(&format!("status {}", directory));
+        assert_eq!(ok, Ok(true));
+        assert!(state.starts_with("inputs: 2\n"), "{}", state);
+        assert!(state.contains("map 1: done\n"), "{}", state);
+        assert!(
+            state.ends_with("done: 4, in-progress: 0, failed: 0\n"),
+            "{}",
+            state
+        );
+
+        let inspected = result_files
+            .iter()
+            .map(|path| command(&format!("inspect {}", path)).1)
+            .collect::<String>();
+        let mut records = inspected.lines().collect::<Vec<&str>>();
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                "{\"key\":\"apple\",\"value\":\"2\"}",
+                "{\"key\":\"banana\",\"value\":\"1\"}",
+                "{\"key\":\"cherry\",\"value\":\"3\"}",
+            ]
+        );
+
+        remove_job_outputs(Path::new(directory)).unwrap();
+        assert!(command(&format!("status {}", directory)).0.is_err());
+        assert!(command(&format!("inspect {}/reduce.1.result", directory))
+            .0
+            .is_err());
+    }
+}
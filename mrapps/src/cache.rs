@@ -0,0 +1,328 @@
+// A cache of map outputs kept across runs. Each input is fingerprinted by
+// its size, modification time and a hash of its contents, together with
+// the version of the app that maps it; a map task whose fingerprint is in
+// the cache is not run, its outputs are linked back into the working
+// directory instead.
+//
+// Entries are only evicted by `prune`, which bounds the cache's size by
+// removing the oldest entries first; a cache nobody prunes grows without
+// limit. The version must change whenever the map function or how its
+// output is partitioned changes.
+
+use std::{
+    fs::{self, create_dir_all, hard_link, read_dir, remove_dir_all, File},
+    hash::Hasher,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// FNV-1a finished with the MurmurHash3 mixer. Unlike `DefaultHasher` it
+/// gives the same hash in every process and build.
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    /// Nanoseconds since the epoch.
+    pub modified: u128,
+    pub content: u64,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+
+        Ok(Fingerprint {
+            size: metadata.len(),
+            modified,
+            content: hash_file(path)?,
+        })
+    }
+}
+
+/// The `StableHasher` hash of a file's contents.
+pub(crate) fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut hasher = StableHasher::default();
+    let mut f = File::open(path)?;
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match f.read(&mut buffer)? {
+            0 => break,
+            n => hasher.write(&buffer[..n]),
+        }
+    }
+    Ok(hasher.finish())
+}
+
+pub struct MapCache {
+    directory: PathBuf,
+    version: String,
+}
+
+impl MapCache {
+    pub fn new(directory: PathBuf, version: &str) -> Self {
+        MapCache {
+            directory,
+            version: version.to_string(),
+        }
+    }
+
+    fn entry(&self, fingerprint: &Fingerprint) -> PathBuf {
+        let mut hasher = StableHasher::default();
+        hasher.write(self.version.as_bytes());
+        hasher.write_u8(0);
+        hasher.write_u64(fingerprint.size);
+        hasher.write_u128(fingerprint.modified);
+        self.directory.join(format!(
+            "{:016x}{:016x}",
+            fingerprint.content,
+            hasher.finish()
+        ))
+    }
+
+    pub fn contains(&self, fingerprint: &Fingerprint) -> bool {
+        self.entry(fingerprint).is_dir()
+    }
+
+    /// Links the cached outputs of map task `job_id` into the working
+    /// directory as `map.<job_id>.reduce.<i>`. Returns false on a miss.
+    pub fn restore(
+        &self,
+        fingerprint: &Fingerprint,
+        job_id: i32,
+        working_directory: &Path,
+    ) -> io::Result<bool> {
+        let entry = self.entry(fingerprint);
+        let partitions = match read_dir(&entry) {
+            Ok(partitions) => partitions,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        for partition in partitions {
+            let partition = partition?;
+            let name = partition.file_name();
+            let Some(index) = name.to_str().and_then(|name| name.strip_prefix("reduce.")) else {
+                continue;
+            };
+            let target = working_directory.join(format!("map.{}.reduce.{}", job_id, index));
+            link_or_copy(&partition.path(), &target)?;
+        }
+        Ok(true)
+    }
+
+    /// Stores the map outputs `map.<job_id>.reduce.<i>` in
+    /// `working_directory` under `fingerprint`.
+    pub fn store(
+        &self,
+        fingerprint: &Fingerprint,
+        job_id: i32,
+        working_directory: &Path,
+    ) -> io::Result<()> {
+        let entry = self.entry(fingerprint);
+        if entry.is_dir() {
+            return Ok(());
+        }
+
+        // Built aside and renamed into place, so a half stored entry is
+        // never found. Concurrent stores each build their own.
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let temporary = self.directory.join(format!(
+            ".{}.{}.{}.tmp",
+            entry.file_name().unwrap().to_string_lossy(),
+            process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        create_dir_all(&temporary)?;
+        let prefix = format!("map.{}.reduce.", job_id);
+        let stored = read_dir(working_directory)
+            .and_then(|outputs| {
+                outputs
+                    .filter_map(|output| output.ok())
+                    .filter_map(|output| {
+                        let name = output.file_name().into_string().ok()?;
+                        let index = name.strip_prefix(&prefix)?.to_string();
+                        Some((output.path(), index))
+                    })
+                    .try_for_each(|(path, index)| {
+                        link_or_copy(&path, &temporary.join(format!("reduce.{}", index)))
+                    })
+            })
+            .and_then(|()| fs::rename(&temporary, &entry));
+        if stored.is_err() {
+            let _ = remove_dir_all(&temporary);
+        }
+        match stored {
+            // Another run stored the same entry first.
+            Err(_) if entry.is_dir() => Ok(()),
+            stored => stored,
+        }
+    }
+
+    /// Removes entries, the least recently stored first, until the cache
+    /// holds at most `max_bytes` of map output.
+    pub fn prune(&self, max_bytes: u64) -> io::Result<()> {
+        let mut entries = vec![];
+        let mut total = 0;
+        for entry in read_dir(&self.directory)? {
+            let entry = entry?;
+            // Entries being stored are not the cache's yet.
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let stored = entry
+                .metadata()?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let mut bytes = 0;
+            for partition in read_dir(entry.path())? {
+                bytes += partition?.metadata()?.len();
+            }
+            total += bytes;
+            entries.push((stored, bytes, entry.path()));
+        }
+
+        entries.sort();
+        for (_, bytes, path) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            remove_dir_all(path)?;
+            total -= bytes;
+        }
+        Ok(())
+    }
+}
+
+// Outputs are replaced by renaming, never rewritten in place, so the
+// cache and the working directory can share files.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(to);
+    hard_link(from, to).or_else(|_| fs::copy(from, to).map(drop))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_to_string, remove_file, write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        keyed::{read_results, Keyed},
+        master::Master,
+        pipeline::remove_job_outputs,
+        worker::KeyValue,
+    };
+
+    fn word_count(directory: &Path, maps: Arc<AtomicUsize>, version: &str) -> Master {
+        let keyed = Keyed::new(2);
+        let mut master = Master::new(
+            directory.to_path_buf(),
+            (1..=3)
+                .map(|i| directory.join(format!("input_{}", i)))
+                .collect(),
+            keyed.map(Arc::new(move |contents: String| {
+                maps.fetch_add(1, Ordering::SeqCst);
+                contents
+                    .split_whitespace()
+                    .map(|word| KeyValue {
+                        key: word.to_string(),
+                        value: "1".to_string(),
+                    })
+                    .collect()
+            })),
+            keyed.reduce(Arc::new(|key, values| {
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: values.count().to_string(),
+                }]
+            })),
+        );
+        master.set_map_cache(MapCache::new(directory.join("cache"), version));
+        master
+    }
+
+    fn counts(result_files: Vec<PathBuf>) -> Vec<KeyValue> {
+        let mut counts = result_files
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .collect::<Vec<KeyValue>>();
+        counts.sort_by(|a, b| a.key.cmp(&b.key));
+        counts
+    }
+
+    #[test]
+    fn cached_map_outputs_are_reused() {
+        let directory = PathBuf::from("./test-data/map_cache_reuses_outputs");
+        let changing = directory.join("input_3");
+        write(&changing, "cherry apple").unwrap();
+        let run = |version: &str| {
+            let maps = Arc::new(AtomicUsize::new(0));
+            let results = word_count(&directory, maps.clone(), version).run(2);
+            let counts = counts(results);
+            remove_job_outputs(&directory).unwrap();
+            (maps.load(Ordering::SeqCst), counts)
+        };
+
+        let (maps, first) = run("1");
+        assert_eq!(maps, 3);
+        assert_eq!(run("1"), (0, first.clone()));
+        assert_eq!(run("2").0, 3);
+
+        write(&changing, "cherry cherry").unwrap();
+        let (maps, changed) = run("2");
+        assert_eq!(maps, 1);
+        let cherry = |counts: &[KeyValue]| {
+            counts
+                .iter()
+                .find(|kv| kv.key == "cherry")
+                .map(|kv| kv.value.clone())
+        };
+        assert_eq!(cherry(&first), Some("1".to_string()));
+        assert_eq!(cherry(&changed), Some("2".to_string()));
+
+        let cache = MapCache::new(directory.join("cache"), "2");
+        cache.prune(u64::MAX).unwrap();
+        assert_eq!(run("2").0, 0);
+        cache.prune(0).unwrap();
+        assert_eq!(run("2").0, 3);
+
+        remove_file(&changing).unwrap();
+        remove_dir_all(directory.join("cache")).unwrap();
+    }
+}
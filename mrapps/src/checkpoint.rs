@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{metadata, read_dir, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{cache::hash_file, status::TaskId};
+
+pub const WAL_NAME: &str = "master.wal";
+
+/// A file a task committed, as it was when the commit was logged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    pub len: u64,
+    /// `StableHasher` hash of the contents.
+    pub checksum: u64,
+}
+
+/// One task state transition as persisted in the write-ahead log.
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    Input(PathBuf),
+    Dispatched(TaskId),
+    Done(TaskId, Vec<Output>),
+    Failed(TaskId),
+}
+
+impl Entry {
+    fn encode(&self) -> String {
+        match self {
+            Entry::Input(path) => format!("input\t{}", escape_path(path)),
+            Entry::Dispatched(id) => format!("dispatch\t{}\t{}", id.kind(), id.index()),
+            Entry::Done(id, outputs) => {
+                let mut line = format!("done\t{}\t{}", id.kind(), id.index());
+                for output in outputs {
+                    line.push_str(&format!(
+                        "\t{}:{}:{:016x}",
+                        output.name, output.len, output.checksum
+                    ));
+                }
+                line
+            }
+            Entry::Failed(id) => format!("failed\t{}\t{}", id.kind(), id.index()),
+        }
+    }
+
+    fn decode(line: &str) -> Option<Entry> {
+        let mut fields = line.split('\t');
+        let tag = fields.next()?;
+        if tag == "input" {
+            return fields.next().and_then(unescape_path).map(Entry::Input);
+        }
+
+        let index = |kind: &str, index: &str| -> Option<TaskId> {
+            let index = index.parse().ok()?;
+            match kind {
+                "map" => Some(TaskId::Map(index)),
+                "reduce" => Some(TaskId::Reduce(index)),
+                _ => None,
+            }
+        };
+        let id = index(fields.next()?, fields.next()?)?;
+
+        match tag {
+            "dispatch" => Some(Entry::Dispatched(id)),
+            "failed" => Some(Entry::Failed(id)),
+            "done" => fields
+                .map(|output| {
+                    let (rest, checksum) = output.rsplit_once(':')?;
+                    let (name, len) = rest.rsplit_once(':')?;
+                    Some(Output {
+                        name: name.to_string(),
+                        len: len.parse().ok()?,
+                        checksum: u64::from_str_radix(checksum, 16).ok()?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(|outputs| Entry::Done(id, outputs)),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `path` on one line of the log: backslashes, ASCII control
+/// characters and bytes that are not UTF-8 are escaped, so any path
+/// survives the round trip.
+fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+fn unescape_path(escaped: &str) -> Option<PathBuf> {
+    let mut bytes = vec![];
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match rest {
+            [b'\\', tail @ ..] => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            [b'x', hi, lo, tail @ ..] => {
+                let digits = [*hi, *lo];
+                let hex = std::str::from_utf8(&digits).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = tail;
+            }
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Append-only log of task transitions kept in the working directory.
+///
+/// Entries are written as they happen but only synced to disk by `sync`,
+/// which the master calls at the end of each phase. A crashed master
+/// loses nothing, its writes are already with the kernel; a crashed
+/// machine may lose the entries since the last sync, and those tasks
+/// simply run again.
+pub struct Wal {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl Wal {
+    pub fn new(working_directory: &Path) -> Self {
+        Wal {
+            path: working_directory.join(WAL_NAME),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Starts a fresh log for a job over `input_files`.
+    pub fn create(&self, input_files: &[PathBuf]) -> io::Result<()> {
+        *self.file.lock().unwrap() = Some(File::create(&self.path)?);
+        for input in input_files {
+            self.record(&Entry::Input(input.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Continues an existing log after a resume.
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    pub fn record(&self, entry: &Entry) -> io::Result<()> {
+        match self.file.lock().unwrap().as_mut() {
+            Some(f) => writeln!(f, "{}", entry.encode()),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes every entry recorded so far durable.
+    pub fn sync(&self) -> io::Result<()> {
+        match self.file.lock().unwrap().as_ref() {
+            Some(f) => f.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Job state rebuilt from a write-ahead log.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub input_files: Vec<PathBuf>,
+    pub committed: HashMap<TaskId, Vec<Output>>,
+}
+
+impl Recovered {
+    pub fn replay(working_directory: &Path) -> io::Result<Self> {
+        let mut recovered = Recovered::default();
+        for entry in read_log(working_directory)? {
+            match entry {
+                Entry::Input(path) => recovered.input_files.push(path),
+                Entry::Done(id, outputs) => {
+                    recovered.committed.insert(id, outputs);
+                }
+                Entry::Dispatched(id) | Entry::Failed(id) => {
+                    recovered.committed.remove(&id);
+                }
+            }
+        }
+
+        Ok(recovered)
+    }
+
+    /// Drops every committed task whose outputs are missing or have a
+    /// different length or checksum than when they were committed. Reduce
+    /// tasks only stay committed if no map task has to run again.
+    pub fn verify(&mut self, working_directory: &Path) {
+        self.committed.retain(|_, outputs| {
+            outputs.iter().all(|output| {
+                let path = working_directory.join(&output.name);
+                metadata(&path).is_ok_and(|m| m.len() == output.len)
+                    && hash_file(&path).is_ok_and(|checksum| checksum == output.checksum)
+            })
+        });
+
+        let n_maps = self.input_files.len();
+        let all_maps_committed = (1..=n_maps as i32).all(|i| self.is_committed(TaskId::Map(i)));
+        if !all_maps_committed {
+            self.committed.retain(|id, _| matches!(id, TaskId::Map(_)));
+        }
+    }
+
+    pub fn is_committed(&self, id: TaskId) -> bool {
+        self.committed.contains_key(&id)
+    }
+}
+
+/// Reads the entries of the write-ahead log in `working_directory`.
+pub fn read_log(working_directory: &Path) -> io::Result<Vec<Entry>> {
+    let f = File::open(working_directory.join(WAL_NAME))?;
+    let mut entries = vec![];
+    // A torn final line from a crash simply fails to decode.
+    for line in BufReader::new(f).lines() {
+        entries.extend(Entry::decode(&line?));
+    }
+    Ok(entries)
+}
+
+/// Lists the files a finished task committed to `working_directory`,
+/// with their lengths.
+pub fn committed_outputs(working_directory: &Path, id: TaskId) -> Vec<(String, u64)> {
+    let prefix = match id {
+        TaskId::Map(i) => format!("map.{}.", i),
+        TaskId::Reduce(i) => format!("reduce.{}.result", i),
+    };
+
+    let mut outputs = read_dir(working_directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    let len = entry.metadata().ok()?.len();
+                    match id {
+                        TaskId::Map(_) if name.starts_with(&prefix) => Some((name, len)),
+                        TaskId::Reduce(_) if name == prefix => Some((name, len)),
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<(String, u64)>>()
+        })
+        .unwrap_or_default();
+    outputs.sort();
+    outputs
+}
+
+/// The files a finished task committed, with their checksums, for its
+/// `Done` entry.
+pub fn checksummed_outputs(working_directory: &Path, id: TaskId) -> Vec<Output> {
+    committed_outputs(working_directory, id)
+        .into_iter()
+        .filter_map(|(name, len)| {
+            let checksum = hash_file(&working_directory.join(&name)).ok()?;
+            Some(Output {
+                name,
+                len,
+                checksum,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip() {
+        let entries = vec![
+            Entry::Input(PathBuf::from("./inputs/a b.txt")),
+            Entry::Input(PathBuf::from("./inputs/tab\there\nnew\\line")),
+            Entry::Input(PathBuf::from(OsString::from_vec(
+                b"./inputs/\xff\xfe.txt".to_vec(),
+            ))),
+            Entry::Dispatched(TaskId::Map(3)),
+            Entry::Done(
+                TaskId::Map(3),
+                vec![
+                    Output {
+                        name: "map.3.reduce.1".to_string(),
+                        len: 12,
+                        checksum: 0x0123_4567_89ab_cdef,
+                    },
+                    Output {
+                        name: "map.3.reduce.2".to_string(),
+                        len: 0,
+                        checksum: 0,
+                    },
+                ],
+            ),
+            Entry::Failed(TaskId::Reduce(2)),
+        ];
+
+        for entry in entries {
+            assert_eq!(Entry::decode(&entry.encode()), Some(entry));
+        }
+        assert_eq!(
+            Entry::decode("done\tmap\t1\tmap.1.reduce.1:1:ff"),
+            Some(Entry::Done(
+                TaskId::Map(1),
+                vec![Output {
+                    name: "map.1.reduce.1".to_string(),
+                    len: 1,
+                    checksum: 0xff,
+                }]
+            ))
+        );
+        assert_eq!(Entry::decode("done\tmap\t1\tmap.1.re"), None);
+        assert_eq!(Entry::decode("done\tmap\t1\tmap.1.reduce.1:1"), None);
+        assert!(!Entry::Input(PathBuf::from("a\tb\nc"))
+            .encode()
+            .contains(['\n', '\r']));
+        assert_eq!(
+            Entry::encode(&Entry::Input(PathBuf::from("a\tb"))),
+            "input\ta\\x09b"
+        );
+    }
+}
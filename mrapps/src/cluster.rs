@@ -0,0 +1,350 @@
+// A long-lived worker pool shared by many jobs. Each submitted job is
+// driven by its own master thread, which queues tasks with the cluster
+// instead of starting workers; pool threads take the next task from the
+// job with the highest priority, sharing equally between jobs of the same
+// priority in proportion to their weights.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use chan::Sender;
+
+use crate::{
+    master::{JobHandle, Master},
+    transport::Channels,
+    worker::{Job, JobResult, Worker},
+};
+
+/// How a job competes with the other jobs on a cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scheduling {
+    /// Jobs with a higher priority run first.
+    pub priority: u32,
+    /// A job's share of the workers relative to jobs of the same priority.
+    pub weight: u32,
+}
+
+impl Default for Scheduling {
+    fn default() -> Self {
+        Scheduling {
+            priority: 0,
+            weight: 1,
+        }
+    }
+}
+
+struct Task {
+    worker: Arc<Worker>,
+    job: Job,
+    results_queue: Sender<JobResult>,
+}
+
+struct Queue {
+    id: u64,
+    scheduling: Scheduling,
+    // Work done so far divided by weight; the queue furthest behind is
+    // served next.
+    virtual_time: f64,
+    tasks: VecDeque<Task>,
+}
+
+#[derive(Default)]
+struct State {
+    queues: Vec<Queue>,
+    next_id: u64,
+    shutdown: bool,
+}
+
+impl State {
+    fn register(&mut self, scheduling: Scheduling) -> u64 {
+        // Joining at the current minimum keeps a new job from claiming
+        // the whole pool while it catches up.
+        let virtual_time = self
+            .queues
+            .iter()
+            .map(|q| q.virtual_time)
+            .fold(None, |min: Option<f64>, t| {
+                Some(min.map_or(t, |m| m.min(t)))
+            })
+            .unwrap_or(0.0);
+        self.next_id += 1;
+        self.queues.push(Queue {
+            id: self.next_id,
+            scheduling,
+            virtual_time,
+            tasks: VecDeque::new(),
+        });
+        self.next_id
+    }
+
+    fn next_task(&mut self) -> Option<Task> {
+        let queue = self
+            .queues
+            .iter_mut()
+            .filter(|q| !q.tasks.is_empty())
+            .min_by(|a, b| {
+                b.scheduling
+                    .priority
+                    .cmp(&a.scheduling.priority)
+                    .then(a.virtual_time.total_cmp(&b.virtual_time))
+            })?;
+        queue.virtual_time += 1.0 / queue.scheduling.weight.max(1) as f64;
+        queue.tasks.pop_front()
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+pub struct Cluster {
+    shared: Arc<Shared>,
+    n_workers: usize,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Cluster {
+    pub fn new(n_workers: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let threads = (0..n_workers)
+            .map(|id| {
+                let shared = shared.clone();
+                thread::spawn(move || work(id, &shared))
+            })
+            .collect();
+        Cluster {
+            shared,
+            n_workers,
+            threads,
+        }
+    }
+
+    /// Runs `master`'s job on the cluster's workers.
+    pub fn submit(&self, master: Master, scheduling: Scheduling) -> JobHandle {
+        let shared = self.shared.clone();
+        let max_outstanding = 2 * self.n_workers.max(1);
+        let id = shared.state.lock().unwrap().register(scheduling);
+        JobHandle::spawn(master, move |master| {
+            let registration = Registration {
+                shared: shared.clone(),
+                id,
+            };
+            let worker = Arc::new(master.worker());
+            let (results_send, results_queue) = chan::r#async();
+            let send = |job| {
+                let mut state = shared.state.lock().unwrap();
+                let queue = state.queues.iter_mut().find(|q| q.id == id).unwrap();
+                queue.tasks.push_back(Task {
+                    worker: worker.clone(),
+                    job,
+                    results_queue: results_send.clone(),
+                });
+                shared.ready.notify_one();
+            };
+
+            let mut transport = Channels {
+                send: &send,
+                results_queue: &results_queue,
+                started: Instant::now(),
+            };
+            let result_files = master.drive(&mut transport, max_outstanding);
+            drop(registration);
+            result_files
+        })
+    }
+
+    /// The number of tasks waiting for a worker.
+    pub fn queued(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.queues.iter().map(|q| q.tasks.len()).sum()
+    }
+}
+
+// Removes a job's queue when its master finishes, also by panicking, so
+// the workers can shut down. The panic itself reaches `JobHandle::wait`.
+struct Registration {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.queues.retain(|q| q.id != self.id);
+        self.shared.ready.notify_all();
+    }
+}
+
+fn work(id: usize, shared: &Shared) {
+    loop {
+        let task = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if let Some(task) = state.next_task() {
+                    break task;
+                }
+                // Jobs still running may queue more tasks.
+                if state.shutdown && state.queues.is_empty() {
+                    return;
+                }
+                state = shared.ready.wait(state).unwrap();
+            }
+        };
+        let result = task.worker.process(id, task.job);
+        task.results_queue.send(result);
+    }
+}
+
+impl Drop for Cluster {
+    /// Waits for the submitted jobs to finish.
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.ready.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{BufReader, Read},
+        panic::{catch_unwind, AssertUnwindSafe},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        pipeline::remove_job_outputs,
+        trace::{Event, Sink},
+        worker::panic_message,
+    };
+
+    fn directory(name: &str) -> PathBuf {
+        PathBuf::from("./test-data/cluster_shares_workers").join(name)
+    }
+
+    // A map-only job over the three inputs in `name` that records the
+    // order its tasks run in.
+    fn recorded_job(name: &'static str, order: &Arc<Mutex<Vec<&'static str>>>) -> Master {
+        let order = order.clone();
+        let input_files = (1..=3)
+            .map(|i| directory(name).join(format!("input_{}", i)))
+            .collect();
+        Master::map_only(
+            directory(name),
+            input_files,
+            Arc::new(move |mut input: BufReader<File>| {
+                order.lock().unwrap().push(name);
+                let mut contents = String::new();
+                input.read_to_string(&mut contents).unwrap();
+                vec![contents]
+            }),
+        )
+    }
+
+    // Occupies the cluster's only worker until the returned sender is
+    // dropped.
+    fn gate(cluster: &Cluster) -> (JobHandle, Sender<()>) {
+        let (started_send, started) = chan::r#async();
+        let (release, release_recv) = chan::r#async::<()>();
+        let job = cluster.submit(
+            Master::map_only(
+                directory("gate"),
+                vec![directory("gate").join("input_1")],
+                Arc::new(move |_| {
+                    started_send.send(());
+                    release_recv.recv();
+                    vec![]
+                }),
+            ),
+            Scheduling::default(),
+        );
+        started.recv().unwrap();
+        (job, release)
+    }
+
+    fn wait_until_queued(cluster: &Cluster, n: usize) {
+        while cluster.queued() < n {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn cluster_shares_workers_between_jobs() {
+        let cluster = Cluster::new(1);
+
+        // Jobs of the same priority take turns.
+        let order = Arc::new(Mutex::new(vec![]));
+        let (gate_job, release) = gate(&cluster);
+        let a = cluster.submit(recorded_job("a", &order), Scheduling::default());
+        let b = cluster.submit(recorded_job("b", &order), Scheduling::default());
+        wait_until_queued(&cluster, 4);
+        drop(release);
+
+        assert_eq!(a.wait().len(), 3);
+        assert_eq!(b.wait().len(), 3);
+        gate_job.wait();
+        assert_eq!(order.lock().unwrap()[..4], ["a", "b", "a", "b"]);
+
+        // A higher priority job goes first.
+        let order = Arc::new(Mutex::new(vec![]));
+        let (gate_job, release) = gate(&cluster);
+        let a = cluster.submit(recorded_job("a", &order), Scheduling::default());
+        let b = cluster.submit(
+            recorded_job("b", &order),
+            Scheduling {
+                priority: 1,
+                ..Scheduling::default()
+            },
+        );
+        wait_until_queued(&cluster, 4);
+        drop(release);
+
+        a.wait();
+        b.wait();
+        gate_job.wait();
+        assert_eq!(order.lock().unwrap()[..2], ["b", "b"]);
+
+        drop(cluster);
+        for name in ["gate", "a", "b"] {
+            remove_job_outputs(&directory(name)).unwrap();
+        }
+    }
+
+    struct PanickingSink;
+
+    impl Sink for PanickingSink {
+        fn record(&self, _event: &Event) {
+            panic!("sink failed");
+        }
+    }
+
+    #[test]
+    fn cluster_survives_panicking_master() {
+        let cluster = Cluster::new(1);
+        let mut master = recorded_job("panic", &Arc::new(Mutex::new(vec![])));
+        master.set_trace_sink(Arc::new(PanickingSink));
+
+        let job = cluster.submit(master, Scheduling::default());
+        let panic = catch_unwind(AssertUnwindSafe(|| job.wait())).unwrap_err();
+        assert_eq!(panic_message(panic), "sink failed");
+
+        // Dropping the cluster waits for its jobs; the failed one is gone.
+        drop(cluster);
+        remove_job_outputs(&directory("panic")).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+use crate::worker::Job;
+
+const SPINS: usize = 16;
+
+/// How the master hands tasks to its workers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dispatch {
+    /// All workers take jobs from one shared queue.
+    #[default]
+    SharedQueue,
+    /// Every worker has its own deque and steals from the others when it
+    /// runs dry.
+    WorkStealing,
+}
+
+/// Per-worker deques. A job goes to the deque of the worker its input
+/// hashes to, so tasks over the same file tend to run on the same worker,
+/// in order. Idle workers steal from the back of other deques.
+pub struct Deques {
+    locals: Vec<Mutex<VecDeque<Job>>>,
+    queued: AtomicUsize,
+    sleeping: AtomicUsize,
+    // Whether the deques are closed; only taken by idle workers, and by
+    // pushes while a worker sleeps.
+    closed: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Deques {
+    pub fn new(n_workers: usize) -> Self {
+        Deques {
+            locals: (0..n_workers.max(1))
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            queued: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            closed: Mutex::new(false),
+            wake: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, job: Job) {
+        let home = self.home(&job);
+        {
+            // Count the job before anyone can take it, so `queued` never
+            // drops below zero.
+            let mut local = self.locals[home].lock().unwrap();
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            local.push_back(job);
+        }
+        // A worker counts itself as sleeping before its last look at
+        // `queued`, so either it sees this job or we see it and wake it.
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _closed = self.closed.lock().unwrap();
+            self.wake.notify_one();
+        }
+    }
+
+    /// Takes the next job for `worker`, waiting for one if every deque is
+    /// empty. Returns `None` once the deques are closed and drained.
+    pub fn pop(&self, worker: usize) -> Option<Job> {
+        loop {
+            // Look around a few times before going to sleep, since waking a
+            // worker costs more than a tiny task.
+            for _ in 0..SPINS {
+                if let Some(job) = self.take(worker) {
+                    self.queued.fetch_sub(1, Ordering::SeqCst);
+                    return Some(job);
+                }
+                thread::yield_now();
+            }
+            let closed = self.closed.lock().unwrap();
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            if self.queued.load(Ordering::SeqCst) == 0 && !*closed {
+                drop(self.wake.wait(closed).unwrap());
+            } else if self.queued.load(Ordering::SeqCst) == 0 {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn close(&self) {
+        *self.closed.lock().unwrap() = true;
+        self.wake.notify_all();
+    }
+
+    fn take(&self, worker: usize) -> Option<Job> {
+        let n = self.locals.len();
+        let own = worker % n;
+        if let Some(job) = self.locals[own].lock().unwrap().pop_front() {
+            return Some(job);
+        }
+        (1..n).find_map(|i| self.locals[(own + i) % n].lock().unwrap().pop_back())
+    }
+
+    fn home(&self, job: &Job) -> usize {
+        let mut hasher = DefaultHasher::new();
+        match job {
+            Job::Map((_, path)) | Job::MapOnly((_, path)) => path.hash(&mut hasher),
+            Job::Reduce((job_id, _)) => job_id.hash(&mut hasher),
+        }
+        hasher.finish() as usize % self.locals.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn workers_keep_their_jobs_and_steal_when_idle() {
+        let deques = Deques::new(2);
+        let job = |id: i32| Job::Map((id, PathBuf::from("split")));
+        for id in 1..=4 {
+            deques.push(job(id));
+        }
+        let home = deques.home(&job(1));
+        let other = 1 - home;
+
+        // The owner works through its deque in order while the idle worker
+        // steals from the other end.
+        assert_eq!(deques.pop(home), Some(job(1)));
+        assert_eq!(deques.pop(other), Some(job(4)));
+        assert_eq!(deques.pop(home), Some(job(2)));
+
+        deques.close();
+        assert_eq!(deques.pop(other), Some(job(3)));
+        assert_eq!(deques.pop(home), None);
+    }
+}
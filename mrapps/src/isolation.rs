@@ -0,0 +1,168 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::FromRawFd,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use crate::worker::panic_message;
+
+/// Resource limits applied to a task running in its own process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum size of the process's data segment and heap, in bytes.
+    pub memory_bytes: Option<u64>,
+    /// Maximum CPU time, in seconds.
+    pub cpu_seconds: Option<u64>,
+}
+
+/// Where workers run the user's map and reduce functions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Execution {
+    /// On the worker thread, inside the master process.
+    #[default]
+    Thread,
+    /// In a child process forked for every task, so a crash or a limit
+    /// violation only fails that task.
+    Process(Limits),
+}
+
+/// Runs `task` in a forked child process under `limits` and waits for it.
+/// Returns why the child failed: its panic message, or the signal or exit
+/// code it died with.
+///
+/// The child is a copy of a multi-threaded process, so `task` must not wait
+/// on locks other threads may have been holding at the fork.
+pub fn run_isolated<F: FnOnce()>(limits: &Limits, task: F) -> Result<(), String> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(format!(
+            "could not create pipe: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    match unsafe { libc::fork() } {
+        -1 => {
+            unsafe {
+                libc::close(read_fd);
+                libc::close(write_fd);
+            }
+            Err(format!(
+                "could not fork: {}",
+                std::io::Error::last_os_error()
+            ))
+        }
+        0 => {
+            unsafe { libc::close(read_fd) };
+            set_limit(libc::RLIMIT_DATA, limits.memory_bytes, 0);
+            // A second of headroom so SIGXCPU arrives before SIGKILL.
+            set_limit(libc::RLIMIT_CPU, limits.cpu_seconds, 1);
+
+            let code = match catch_unwind(AssertUnwindSafe(task)) {
+                Ok(()) => 0,
+                Err(panic) => {
+                    let message = panic_message(panic);
+                    let mut pipe = unsafe { File::from_raw_fd(write_fd) };
+                    let _ = pipe.write_all(message.as_bytes());
+                    101
+                }
+            };
+            // Skip destructors and exit handlers that belong to the parent.
+            unsafe { libc::_exit(code) }
+        }
+        pid => {
+            unsafe { libc::close(write_fd) };
+            let mut message = String::new();
+            let _ = unsafe { File::from_raw_fd(read_fd) }.read_to_string(&mut message);
+
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+                return Err(format!(
+                    "could not wait for task process: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            describe_exit(status, message)
+        }
+    }
+}
+
+fn set_limit(resource: libc::__rlimit_resource_t, limit: Option<u64>, headroom: u64) {
+    if let Some(limit) = limit {
+        let rlimit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit + headroom,
+        };
+        unsafe { libc::setrlimit(resource, &rlimit) };
+    }
+}
+
+fn describe_exit(status: i32, message: String) -> Result<(), String> {
+    if libc::WIFEXITED(status) {
+        return match libc::WEXITSTATUS(status) {
+            0 => Ok(()),
+            _ if !message.is_empty() => Err(message),
+            code => Err(format!("task process exited with code {}", code)),
+        };
+    }
+
+    let signal = libc::WTERMSIG(status);
+    Err(match signal {
+        libc::SIGXCPU => "task exceeded its CPU time limit".to_string(),
+        // Allocation failures abort the process.
+        libc::SIGABRT => "task process aborted, possibly out of memory".to_string(),
+        libc::SIGSEGV => "task process crashed with a segmentation fault".to_string(),
+        libc::SIGKILL => "task process was killed".to_string(),
+        signal => format!("task process died from signal {}", signal),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
+
+    use super::*;
+
+    #[test]
+    fn isolated_task_reports_outcome() {
+        let limits = Limits::default();
+
+        assert_eq!(run_isolated(&limits, || {}), Ok(()));
+        assert_eq!(
+            run_isolated(&limits, || panic!("bad input")),
+            Err("bad input".to_string())
+        );
+        assert_eq!(
+            run_isolated(&limits, || std::process::abort()),
+            Err("task process aborted, possibly out of memory".to_string())
+        );
+    }
+
+    #[test]
+    fn isolated_task_is_limited() {
+        let memory = Limits {
+            memory_bytes: Some(64 << 20),
+            cpu_seconds: None,
+        };
+        assert!(run_isolated(&memory, || {
+            black_box(vec![1u8; 1 << 30]);
+        })
+        .is_err());
+
+        let cpu = Limits {
+            memory_bytes: None,
+            cpu_seconds: Some(1),
+        };
+        assert_eq!(
+            run_isolated(&cpu, || {
+                let mut n: u64 = 0;
+                loop {
+                    n = black_box(n.wrapping_add(1));
+                }
+            }),
+            Err("task exceeded its CPU time limit".to_string())
+        );
+    }
+}
@@ -0,0 +1,256 @@
+// Reduce-side joins. Map output keys of every input set become
+// "key<TAB>tag" so that, with a secondary sort, a reducer sees all values
+// for a key grouped by the set they came from.
+
+use std::{error::Error, fmt, path::PathBuf, sync::Arc};
+
+use crate::{
+    keyed::{Keyed, KeyedMap},
+    master::{InputSet, Master},
+    partition::hash_partitioner,
+    worker::KeyValue,
+};
+
+/// Input files whose records take part in a join as `tag`. Tags must be
+/// distinct and must not contain tabs.
+pub struct TaggedInput {
+    pub tag: String,
+    pub input_files: Vec<PathBuf>,
+    pub map: KeyedMap,
+}
+
+/// Reduces one key given its values from every input set, in the order
+/// the sets were passed to `master`. Sets without values for the key get
+/// an empty list.
+pub type SourcesReduce = Arc<dyn Fn(&str, &[Vec<String>]) -> Vec<KeyValue> + Send + Sync>;
+
+fn split_tagged(key: &str) -> (&str, &str) {
+    key.rsplit_once('\t').unwrap_or((key, ""))
+}
+
+/// Builds a master joining `inputs` on their map output keys.
+pub fn master(
+    working_directory: PathBuf,
+    n_reduce: usize,
+    inputs: Vec<TaggedInput>,
+    reduce: SourcesReduce,
+) -> Master {
+    let tags = Arc::new(
+        inputs
+            .iter()
+            .map(|input| input.tag.clone())
+            .collect::<Vec<String>>(),
+    );
+    let source = {
+        let tags = tags.clone();
+        move |key: &str| {
+            let (key, tag) = split_tagged(key);
+            (key.to_string(), tags.iter().position(|t| t == tag))
+        }
+    };
+
+    let hash = hash_partitioner();
+    let sort_source = source.clone();
+    let keyed = Keyed {
+        n_reduce,
+        partitioner: Arc::new(move |key, n| hash(split_tagged(key).0, n)),
+        sort: Arc::new(move |a, b| sort_source(a).cmp(&sort_source(b))),
+        group: Arc::new(|a, b| split_tagged(a).0.cmp(split_tagged(b).0)),
+        combiner: None,
+    };
+
+    let input_sets = inputs
+        .into_iter()
+        .map(|input| {
+            let (map, tag) = (input.map, input.tag);
+            InputSet {
+                input_files: input.input_files,
+                map: keyed.map(Arc::new(move |contents| {
+                    map(contents)
+                        .into_iter()
+                        .map(|kv| KeyValue {
+                            key: format!("{}\t{}", kv.key, tag),
+                            value: kv.value,
+                        })
+                        .collect()
+                })),
+            }
+        })
+        .collect();
+
+    let reduce = keyed.reduce(Arc::new(move |key, records| {
+        let mut sources = vec![vec![]; tags.len()];
+        for kv in records {
+            if let (_, Some(index)) = source(&kv.key) {
+                sources[index].push(kv.value);
+            }
+        }
+        reduce(split_tagged(key).0, &sources)
+    }));
+
+    Master::with_input_sets(working_directory, input_sets, reduce)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    LeftOuter,
+    FullOuter,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    /// A two-way join was given this many input sets instead of two.
+    Sources(usize),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Sources(n) => write!(f, "a join takes two input sets, got {}", n),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// Builds a master joining two input sets, the first being the left side.
+/// Each matching pair of values becomes one record whose value is the left
+/// and right value separated by a tab; a missing side of an outer join is
+/// left empty.
+pub fn join_master(
+    working_directory: PathBuf,
+    n_reduce: usize,
+    inputs: Vec<TaggedInput>,
+    kind: JoinKind,
+) -> Result<Master, JoinError> {
+    if inputs.len() != 2 {
+        return Err(JoinError::Sources(inputs.len()));
+    }
+    Ok(master(working_directory, n_reduce, inputs, join(kind)))
+}
+
+// Only for exactly two sources, which `join_master` checks.
+fn join(kind: JoinKind) -> SourcesReduce {
+    Arc::new(move |key, sources| {
+        let empty = vec![String::new()];
+        let (left, right) = (&sources[0], &sources[1]);
+        let (left, right) = match (kind, left.is_empty(), right.is_empty()) {
+            (_, false, false) => (left, right),
+            (JoinKind::LeftOuter, false, true) | (JoinKind::FullOuter, false, true) => {
+                (left, &empty)
+            }
+            (JoinKind::FullOuter, true, false) => (&empty, right),
+            _ => return vec![],
+        };
+
+        left.iter()
+            .flat_map(|l| {
+                right.iter().map(move |r| KeyValue {
+                    key: key.to_string(),
+                    value: format!("{}\t{}", l, r),
+                })
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::{keyed::read_results, pipeline::remove_job_outputs};
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn csv_map(contents: String) -> Vec<KeyValue> {
+        contents
+            .lines()
+            .filter_map(|line| line.split_once(','))
+            .map(|(key, value)| kv(key, value))
+            .collect()
+    }
+
+    #[test]
+    fn join_kinds() {
+        let both = vec![
+            vec!["alice".to_string()],
+            vec!["login".to_string(), "buy".to_string()],
+        ];
+        let left_only = vec![vec!["bob".to_string()], vec![]];
+        let right_only = vec![vec![], vec!["view".to_string()]];
+
+        let inner = join(JoinKind::Inner);
+        assert_eq!(
+            inner("1", &both),
+            vec![kv("1", "alice\tlogin"), kv("1", "alice\tbuy")]
+        );
+        assert!(inner("2", &left_only).is_empty());
+
+        let left = join(JoinKind::LeftOuter);
+        assert_eq!(left("2", &left_only), vec![kv("2", "bob\t")]);
+        assert!(left("3", &right_only).is_empty());
+
+        let full = join(JoinKind::FullOuter);
+        assert_eq!(full("2", &left_only), vec![kv("2", "bob\t")]);
+        assert_eq!(full("3", &right_only), vec![kv("3", "\tview")]);
+    }
+
+    #[test]
+    fn master_joins_tagged_inputs() {
+        let working_directory = PathBuf::from("./test-data/join_tagged_inputs");
+        let inputs = vec![
+            TaggedInput {
+                tag: "users".to_string(),
+                input_files: vec![working_directory.join("users")],
+                map: Arc::new(csv_map),
+            },
+            TaggedInput {
+                tag: "events".to_string(),
+                input_files: vec![
+                    working_directory.join("events_1"),
+                    working_directory.join("events_2"),
+                ],
+                map: Arc::new(csv_map),
+            },
+        ];
+
+        let master =
+            join_master(working_directory.clone(), 2, inputs, JoinKind::FullOuter).unwrap();
+        let mut results = master
+            .run(2)
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .collect::<Vec<KeyValue>>();
+        results.sort_by(|a, b| (&a.key, &a.value).cmp(&(&b.key, &b.value)));
+
+        assert_eq!(
+            results,
+            vec![
+                kv("1", "alice\tbuy"),
+                kv("1", "alice\tlogin"),
+                kv("2", "bob\t"),
+                kv("3", "carol\tview"),
+                kv("4", "\tlogout"),
+            ]
+        );
+
+        remove_job_outputs(&working_directory).unwrap();
+
+        let users = TaggedInput {
+            tag: "users".to_string(),
+            input_files: vec![working_directory.join("users")],
+            map: Arc::new(csv_map),
+        };
+        assert_eq!(
+            join_master(working_directory, 2, vec![users], JoinKind::Inner).err(),
+            Some(JoinError::Sources(1))
+        );
+    }
+}
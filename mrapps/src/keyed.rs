@@ -0,0 +1,343 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufReader, Read},
+    iter::Peekable,
+    sync::Arc,
+};
+
+use crate::{
+    partition::{hash_partitioner, Partitioner},
+    record::{decode, encode, Records},
+    worker::{fail_task, KeyValue},
+};
+
+pub type Comparator = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+pub type KeyedMap = Arc<dyn Fn(String) -> Vec<KeyValue> + Send + Sync>;
+pub type KeyedReduce =
+    Arc<dyn Fn(&str, &mut dyn Iterator<Item = KeyValue>) -> Vec<KeyValue> + Send + Sync>;
+
+pub fn natural_order() -> Comparator {
+    Arc::new(|a: &str, b: &str| a.cmp(b))
+}
+
+/// Turns key/value map and reduce functions into the file based functions
+/// `Master` runs.
+///
+/// Map output is partitioned with `partitioner` and each partition is
+/// sorted with `sort`. A reduce task merges its sorted partitions and calls
+/// the reduce function once per run of keys that `group` considers equal,
+/// streaming the records of that run in `sort` order. For a secondary sort,
+/// `sort` orders composite keys fully, `group` compares only their leading
+/// part, and `partitioner` must send keys of the same group to the same
+/// partition.
+///
+/// A `combiner` runs over every group of a sorted partition before it is
+/// written, so it must be safe to apply to partial groups any number of
+/// times, as an associative sum is.
+#[derive(Clone)]
+pub struct Keyed {
+    pub n_reduce: usize,
+    pub partitioner: Partitioner,
+    pub sort: Comparator,
+    pub group: Comparator,
+    pub combiner: Option<KeyedReduce>,
+}
+
+impl Keyed {
+    pub fn new(n_reduce: usize) -> Self {
+        Keyed {
+            n_reduce,
+            partitioner: hash_partitioner(),
+            sort: natural_order(),
+            group: natural_order(),
+            combiner: None,
+        }
+    }
+
+    pub fn map(&self, map: KeyedMap) -> Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync> {
+        let keyed = self.clone();
+        Arc::new(move |mut input: BufReader<File>| {
+            let mut contents = String::new();
+            if let Err(e) = input.read_to_string(&mut contents) {
+                fail_task(e);
+            }
+            keyed.partition(map(contents))
+        })
+    }
+
+    /// Splits records into `n_reduce` sorted, encoded partitions. Without
+    /// reduce tasks the records stay in one partition, in map order.
+    pub fn partition(&self, records: Vec<KeyValue>) -> Vec<String> {
+        if self.n_reduce == 0 {
+            return vec![records
+                .iter()
+                .map(|kv| format!("{}\n", encode(kv)))
+                .collect()];
+        }
+
+        let mut partitions = vec![vec![]; self.n_reduce];
+        for kv in records {
+            let index = (self.partitioner)(&kv.key, self.n_reduce);
+            partitions[index].push(kv);
+        }
+
+        partitions
+            .into_iter()
+            .map(|mut partition| {
+                partition.sort_by(|a, b| (self.sort)(&a.key, &b.key));
+                if let Some(combiner) = &self.combiner {
+                    let mut combined = vec![];
+                    self.for_each_group(partition.into_iter(), |key, values| {
+                        combined.extend(combiner(key, values));
+                    });
+                    partition = combined;
+                }
+                partition
+                    .iter()
+                    .map(|kv| format!("{}\n", encode(kv)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn reduce(
+        &self,
+        reduce: KeyedReduce,
+    ) -> Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync> {
+        let keyed = self.clone();
+        Arc::new(move |inputs: Vec<BufReader<File>>| {
+            let sources = inputs.into_iter().map(records).collect();
+            let mut output = String::new();
+            keyed.for_each_group(Merge::new(sources, keyed.sort.clone()), |key, values| {
+                for kv in reduce(key, values) {
+                    output.push_str(&encode(&kv));
+                    output.push('\n');
+                }
+            });
+            output
+        })
+    }
+
+    /// Calls `f` with the first key and the records of every group in an
+    /// already sorted stream. Records `f` leaves unread are skipped.
+    pub fn for_each_group<I, F>(&self, records: I, mut f: F)
+    where
+        I: Iterator<Item = KeyValue>,
+        F: FnMut(&str, &mut dyn Iterator<Item = KeyValue>),
+    {
+        let mut records = records.peekable();
+        while let Some(first) = records.peek() {
+            let key = first.key.clone();
+            let mut group = Group {
+                records: &mut records,
+                key: &key,
+                group: &self.group,
+            };
+            f(&key, &mut group);
+            group.for_each(drop);
+        }
+    }
+}
+
+/// The records of an intermediate file. One that cannot be read or decoded
+/// fails the task rather than silently ending its partition early.
+pub(crate) fn records(input: BufReader<File>) -> impl Iterator<Item = KeyValue> {
+    Records::new(input).map(|record| record.unwrap_or_else(|e| fail_task(e)))
+}
+
+/// Merges sorted record streams into one sorted stream. Equal keys come out
+/// in source order.
+pub struct Merge<I: Iterator<Item = KeyValue>> {
+    sources: Vec<Peekable<I>>,
+    sort: Comparator,
+}
+
+impl<I: Iterator<Item = KeyValue>> Merge<I> {
+    pub fn new(sources: Vec<I>, sort: Comparator) -> Self {
+        Merge {
+            sources: sources.into_iter().map(|s| s.peekable()).collect(),
+            sort,
+        }
+    }
+}
+
+impl<I: Iterator<Item = KeyValue>> Iterator for Merge<I> {
+    type Item = KeyValue;
+
+    fn next(&mut self) -> Option<KeyValue> {
+        let mut min: Option<(usize, &KeyValue)> = None;
+        for (index, source) in self.sources.iter_mut().enumerate() {
+            if let Some(kv) = source.peek() {
+                match min {
+                    Some((_, m)) if (self.sort)(&kv.key, &m.key) != Ordering::Less => {}
+                    _ => min = Some((index, kv)),
+                }
+            }
+        }
+        let index = min?.0;
+        self.sources[index].next()
+    }
+}
+
+struct Group<'a, I: Iterator<Item = KeyValue>> {
+    records: &'a mut Peekable<I>,
+    key: &'a str,
+    group: &'a Comparator,
+}
+
+impl<I: Iterator<Item = KeyValue>> Iterator for Group<'_, I> {
+    type Item = KeyValue;
+
+    fn next(&mut self) -> Option<KeyValue> {
+        self.records
+            .next_if(|kv| (self.group)(self.key, &kv.key) == Ordering::Equal)
+    }
+}
+
+/// Reads the records of a keyed result file.
+pub fn read_results(contents: &str) -> Vec<KeyValue> {
+    contents.lines().filter_map(decode).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_to_string, remove_file},
+        panic::{catch_unwind, AssertUnwindSafe},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::{checkpoint::WAL_NAME, master::Master, worker::panic_message};
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn reduce_fails_task_on_corrupt_input() {
+        let keyed = Keyed::new(1);
+        let reduce = keyed.reduce(Arc::new(|key, values| {
+            vec![kv(key, &values.count().to_string())]
+        }));
+        let input = PathBuf::from("./test-data/keyed_reduce_rejects_corrupt_input/map.1.reduce.1");
+
+        let failure = catch_unwind(AssertUnwindSafe(|| {
+            reduce(vec![BufReader::new(File::open(&input).unwrap())])
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(failure), "line 2: not a record: \"broken\"");
+    }
+
+    #[test]
+    fn merge_groups_sorted_streams() {
+        let keyed = Keyed::new(1);
+        let merged = Merge::new(
+            vec![
+                vec![kv("a", "1"), kv("c", "1")].into_iter(),
+                vec![kv("a", "2"), kv("b", "2"), kv("c", "2")].into_iter(),
+            ],
+            natural_order(),
+        );
+
+        let mut groups = vec![];
+        keyed.for_each_group(merged, |key, values| {
+            // Only look at the first value; the rest must be skipped.
+            groups.push((key.to_string(), values.next().unwrap().value));
+        });
+
+        assert_eq!(
+            groups,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string()),
+                ("c".to_string(), "1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn combiner_folds_groups_within_a_partition() {
+        let keyed = Keyed {
+            combiner: Some(Arc::new(|key, values| {
+                let sum = values
+                    .map(|kv| kv.value.parse::<u32>().unwrap())
+                    .sum::<u32>();
+                vec![kv(key, &sum.to_string())]
+            })),
+            ..Keyed::new(1)
+        };
+
+        let partitions = keyed.partition(vec![kv("b", "1"), kv("a", "2"), kv("b", "3")]);
+        assert_eq!(partitions, vec![format!("a\t2\nb\t4\n")]);
+    }
+
+    // Events are keyed by "user|timestamp"; users group together and each
+    // user's events arrive in timestamp order.
+    fn user(key: &str) -> &str {
+        key.split('|').next().unwrap()
+    }
+
+    fn timestamp(key: &str) -> u64 {
+        key.split('|').nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn reduce_receives_values_in_secondary_order() {
+        let working_directory = PathBuf::from("./test-data/keyed_secondary_sort");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+
+        let hash = hash_partitioner();
+        let keyed = Keyed {
+            n_reduce: 2,
+            partitioner: Arc::new(move |key, n| hash(user(key), n)),
+            sort: Arc::new(|a, b| user(a).cmp(user(b)).then(timestamp(a).cmp(&timestamp(b)))),
+            group: Arc::new(|a, b| user(a).cmp(user(b))),
+            combiner: None,
+        };
+        let map = keyed.map(Arc::new(|contents: String| {
+            contents
+                .lines()
+                .map(|line| {
+                    let fields = line.split(',').collect::<Vec<&str>>();
+                    kv(&format!("{}|{}", fields[0], fields[1]), fields[2])
+                })
+                .collect()
+        }));
+        let reduce = keyed.reduce(Arc::new(|key, events| {
+            let events = events.map(|kv| kv.value).collect::<Vec<String>>();
+            vec![kv(user(key), &events.join(","))]
+        }));
+
+        let result_files = Master::new(working_directory.clone(), input_files, map, reduce).run(2);
+
+        let mut results = result_files
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .collect::<Vec<KeyValue>>();
+        results.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            results,
+            vec![
+                kv("alice", "login,view,buy,logout"),
+                kv("bob", "login,logout"),
+                kv("carol", "view"),
+            ]
+        );
+
+        for i in 1..(2 + 1) {
+            for j in 1..(2 + 1) {
+                let _ = remove_file(working_directory.join(format!("map.{}.reduce.{}", j, i)));
+            }
+            let _ = remove_file(working_directory.join(format!("reduce.{}.result", i)));
+        }
+        let _ = remove_file(working_directory.join(WAL_NAME));
+    }
+}
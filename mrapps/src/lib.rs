@@ -1,3 +1,27 @@
+pub mod cache;
+pub mod checkpoint;
+pub mod cluster;
+pub mod dispatch;
+pub mod isolation;
+pub mod join;
+pub mod keyed;
 pub mod master;
+pub mod output;
+pub mod partition;
+pub mod pipeline;
+pub mod plan;
+pub mod record;
+pub mod reducers;
+pub mod remote;
+pub mod retry;
+pub mod shuffle;
+pub mod simulation;
+pub mod sketch;
+pub mod spec;
+pub mod status;
+pub mod streaming;
+pub mod terasort;
+pub mod trace;
+pub mod transport;
 pub mod wc;
 pub mod worker;
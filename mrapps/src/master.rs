@@ -1,25 +1,82 @@
 use std::{
     collections::HashMap,
-    fs::{read_dir, File},
-    io::BufReader,
+    fs::{create_dir_all, metadata, read_dir, remove_file, File},
+    io::{self, BufReader},
+    net::{SocketAddr, ToSocketAddrs},
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
     path::PathBuf,
-    sync::Arc,
-    thread,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use chan::{Receiver, Sender};
+use crate::{
+    cache::{Fingerprint, MapCache},
+    checkpoint::{checksummed_outputs, committed_outputs, Entry, Recovered, Wal},
+    dispatch::{Deques, Dispatch},
+    isolation::Execution,
+    keyed::Keyed,
+    output::{merge_results, OutputFormat, MERGED_NAME},
+    partition::{range_partitioner, HASH_PARTITIONER},
+    plan::{sample_indices, Estimate, MapTask, Plan},
+    retry::RetryPolicy,
+    shuffle::{MemoryShuffle, Shuffle},
+    spec::{self, JobSpec, PartitionerKind},
+    status::{JobStatus, Phase, Snapshot, StatusServer, TaskId, TaskState},
+    terasort::{split_points, SAMPLES_PER_FILE},
+    trace::{micros, EventKind, Sink, Tracer},
+    transport::{Channels, Received, Transport},
+    worker::{panic_message, Job, JobResult, MapFn, Worker},
+};
+
+/// How many times a task is attempted before it is marked failed.
+pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+struct Outstanding {
+    job: Job,
+    attempt: usize,
+    deadline: Option<Duration>,
+}
 
-use crate::worker::{Job, JobResult, Worker};
+/// Input files that are all read by the same map function.
+pub struct InputSet {
+    pub input_files: Vec<PathBuf>,
+    pub map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+}
 
 pub struct Master {
     input_files: Vec<PathBuf>,
     working_directory: PathBuf,
     map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+    input_maps: Arc<HashMap<i32, MapFn>>,
     reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
-    job_queue: Sender<Job>,
-    results_queue: Receiver<JobResult>,
-    worker_job_queue: Receiver<Job>,
-    worker_results_queue: Sender<JobResult>,
+    status: JobStatus,
+    status_server: Option<StatusServer>,
+    wal: Wal,
+    recovered: Mutex<Option<Recovered>>,
+    map_only: bool,
+    output_format: OutputFormat,
+    merge_output: bool,
+    execution: Execution,
+    dispatch: Dispatch,
+    shuffle: Shuffle,
+    memory_shuffle: MemoryShuffle,
+    max_attempts: usize,
+    task_timeout: Option<Duration>,
+    // Tasks waiting for a result.
+    outstanding: Mutex<HashMap<TaskId, Outstanding>>,
+    cancelled: Arc<AtomicBool>,
+    map_cache: Option<MapCache>,
+    tracer: Option<Tracer>,
+    io_retry: RetryPolicy,
+    // Known for jobs built from a spec; only used to plan.
+    n_reduce: Option<usize>,
+    split_points: Vec<String>,
+    // Fingerprints of the inputs being mapped, to cache their outputs.
+    fingerprints: Mutex<HashMap<i32, Fingerprint>>,
 }
 
 impl Master {
@@ -29,101 +86,743 @@ impl Master {
         map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
         reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
     ) -> Self {
-        let (work_send, work_recv) = chan::r#async();
-        let (result_send, result_recv) = chan::r#async();
-
         Master {
             input_files,
+            wal: Wal::new(&working_directory),
             working_directory,
             map,
+            input_maps: Arc::new(HashMap::new()),
             reduce,
-            job_queue: work_send,
-            results_queue: result_recv,
-            worker_job_queue: work_recv,
-            worker_results_queue: result_send,
+            status: JobStatus::default(),
+            status_server: None,
+            recovered: Mutex::new(None),
+            map_only: false,
+            output_format: OutputFormat::Text,
+            merge_output: false,
+            execution: Execution::Thread,
+            dispatch: Dispatch::SharedQueue,
+            shuffle: Shuffle::Disk,
+            memory_shuffle: MemoryShuffle::default(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            task_timeout: None,
+            outstanding: Mutex::new(HashMap::new()),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            map_cache: None,
+            tracer: None,
+            io_retry: RetryPolicy::default(),
+            n_reduce: None,
+            split_points: vec![],
+            fingerprints: Mutex::new(HashMap::new()),
         }
     }
 
-    fn do_map(&self) -> i32 {
-        for (index, input) in self.input_files.iter().enumerate() {
-            self.job_queue
-                .send(Job::Map(((index + 1) as i32, input.clone())));
-        }
-
-        self.input_files.iter().len() as i32
-    }
-
-    fn do_reduce(&self) -> i32 {
-        if let Ok(entries) = read_dir(self.working_directory.clone()) {
-            let groups = entries.filter_map(|entry| entry.ok()).fold(
-                HashMap::new(),
-                |mut grouped, entry| {
-                    let _ = entry
-                        .file_name()
-                        .into_string()
-                        .and_then(|filename| {
-                            filename
-                                .split(".")
-                                .nth(3)
-                                .and_then(|i| i.parse().ok())
-                                .ok_or(entry.file_name())
-                        })
-                        .map(|key| {
-                            let files = grouped.entry(key).or_insert(vec![]);
-                            files.push(entry.path())
-                        });
-                    grouped
-                },
-            );
-            let n_reduce_jobs = groups.iter().len();
-            for (index, group) in groups {
-                self.job_queue.send(Job::Reduce((index, group)));
+    /// Creates a master for a job with no reduce tasks. Each map task
+    /// writes its output, all partitions concatenated, straight to its
+    /// final result file `map.<id>.result`.
+    pub fn map_only(
+        working_directory: PathBuf,
+        input_files: Vec<PathBuf>,
+        map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+    ) -> Self {
+        let mut master = Master::new(
+            working_directory,
+            input_files,
+            map,
+            Arc::new(|_| String::new()),
+        );
+        master.map_only = true;
+        master
+    }
+
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        self.output_format = output_format;
+    }
+
+    /// Merges the result files of a run in which every task succeeded into
+    /// `job.output`, in partition order, and returns only that file. With
+    /// an order-preserving partitioner, such as a range partitioner, the
+    /// merged file is in global key order. A run that fails, is cancelled
+    /// or cannot merge returns no files; its status says why.
+    pub fn set_merge_output(&mut self, merge_output: bool) {
+        self.merge_output = merge_output;
+    }
+
+    /// Runs every task in a forked process, so a crash or a resource
+    /// limit violation fails the task instead of the master.
+    pub fn set_execution(&mut self, execution: Execution) {
+        self.execution = execution;
+    }
+
+    /// Sends a task out again, as another attempt, if it has not reported
+    /// back within `timeout`. Whichever attempt finishes first counts.
+    pub fn set_task_timeout(&mut self, timeout: Duration) {
+        self.task_timeout = Some(timeout);
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: Shuffle) {
+        self.shuffle = shuffle;
+    }
+
+    // Forked tasks cannot hand memory back to the master.
+    pub(crate) fn shuffles_in_memory(&self) -> bool {
+        self.shuffle == Shuffle::Memory && self.execution == Execution::Thread
+    }
+
+    pub fn set_dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatch = dispatch;
+    }
+
+    /// Keeps map outputs in `cache` and reuses them for inputs that have
+    /// not changed since. Only used with the disk shuffle and reduce tasks.
+    pub fn set_map_cache(&mut self, cache: MapCache) {
+        self.map_cache = Some(cache);
+    }
+
+    /// Sends the task lifecycle events of every run to `sink`.
+    pub fn set_trace_sink(&mut self, sink: Arc<dyn Sink>) {
+        self.tracer = Some(Tracer::new(sink));
+    }
+
+    /// Sets how workers retry transient I/O errors, such as running out of
+    /// file descriptors, before they fail the task.
+    pub fn set_io_retry(&mut self, policy: RetryPolicy) {
+        self.io_retry = policy;
+    }
+
+    /// Sets how many times a failed task is attempted before the failure
+    /// is final. At least one attempt is always made.
+    pub fn set_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = max_attempts.max(1);
+    }
+
+    /// Creates a master whose inputs come from several sets, each mapped by
+    /// its own function. Map task ids follow the order of `input_sets`.
+    pub fn with_input_sets(
+        working_directory: PathBuf,
+        input_sets: Vec<InputSet>,
+        reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
+    ) -> Self {
+        let mut input_files = vec![];
+        let mut input_maps = HashMap::new();
+        for set in input_sets {
+            for input in set.input_files {
+                input_files.push(input);
+                input_maps.insert(input_files.len() as i32, set.map.clone());
             }
-            n_reduce_jobs as i32
+        }
+
+        let mut master = Master::new(working_directory, input_files, Arc::new(|_| vec![]), reduce);
+        master.input_maps = Arc::new(input_maps);
+        master
+    }
+
+    /// Creates a master for the job `spec` describes, creating its working
+    /// directory. A range partitioner samples the inputs first.
+    pub fn from_spec(spec: &JobSpec) -> io::Result<Self> {
+        let app = spec::app(&spec.app).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown app `{}`", spec.app),
+            )
+        })?;
+        create_dir_all(&spec.working_directory)?;
+
+        let mut keyed = Keyed::new(spec.n_reduce);
+        let mut splits = vec![];
+        if spec.partitioner == PartitionerKind::Range && spec.n_reduce > 0 {
+            splits = split_points(&spec.input_files, spec.n_reduce, SAMPLES_PER_FILE)?;
+            keyed.partitioner = range_partitioner(splits.clone());
+        }
+        if spec.combiner {
+            keyed.combiner = app.combiner;
+        }
+
+        let working_directory = spec.working_directory.clone();
+        let input_files = spec.input_files.clone();
+        let mut master = if spec.n_reduce == 0 {
+            Master::map_only(working_directory, input_files, keyed.map(app.map))
         } else {
-            0
+            Master::new(
+                working_directory,
+                input_files,
+                keyed.map(app.map),
+                keyed.reduce(app.reduce),
+            )
+        };
+        master.set_output_format(spec.output_format);
+        master.set_merge_output(spec.merge_output);
+        if let Some(timeout) = spec.task_timeout {
+            master.set_task_timeout(timeout);
+        }
+        if let Some(max_attempts) = spec.max_attempts {
+            master.set_max_attempts(max_attempts);
+        }
+        if let Some(directory) = &spec.map_cache {
+            // Everything that shapes map output.
+            let partitioner = match spec.partitioner {
+                PartitionerKind::Hash => HASH_PARTITIONER.to_string(),
+                PartitionerKind::Range => format!("range {:?}", splits),
+            };
+            let version = format!(
+                "{} {} {} {} {}",
+                spec.app,
+                env!("CARGO_PKG_VERSION"),
+                spec.n_reduce,
+                spec.combiner,
+                partitioner
+            );
+            master.set_map_cache(MapCache::new(directory.clone(), &version));
+        }
+        master.n_reduce = Some(spec.n_reduce);
+        master.split_points = splits;
+        Ok(master)
+    }
+
+    /// Rebuilds a master from the write-ahead log a previous `run` left in
+    /// `working_directory`. The next `run` only schedules the tasks whose
+    /// committed outputs are not all still on disk.
+    pub fn resume(
+        working_directory: PathBuf,
+        map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+        reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
+    ) -> io::Result<Self> {
+        let mut recovered = Recovered::replay(&working_directory)?;
+        recovered.verify(&working_directory);
+
+        let master = Master::new(
+            working_directory,
+            recovered.input_files.clone(),
+            map,
+            reduce,
+        );
+        *master.recovered.lock().unwrap() = Some(recovered);
+        Ok(master)
+    }
+
+    /// Serves the live job status over HTTP on `addr` until the master is
+    /// dropped. Returns the bound address, so port 0 may be used.
+    pub fn serve_status<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        let server = StatusServer::bind(addr, self.status.clone())?;
+        let local_addr = server.local_addr();
+        self.status_server = Some(server);
+        Ok(local_addr)
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status.clone()
+    }
+
+    /// Works out what `run` would do without running any task. Up to
+    /// `sample_inputs` inputs are mapped, in memory, to estimate the size
+    /// of the map output.
+    pub fn plan(&self, sample_inputs: usize) -> io::Result<Plan> {
+        let mut map_tasks = vec![];
+        for (index, input) in self.input_files.iter().enumerate() {
+            let cached = match self.map_cache() {
+                Some(cache) => cache.contains(&Fingerprint::of(input)?),
+                None => false,
+            };
+            map_tasks.push(MapTask {
+                id: index as i32 + 1,
+                input: input.clone(),
+                bytes: metadata(input)?.len(),
+                cached,
+            });
+        }
+
+        let mut n_reduce = match self.map_only {
+            true => Some(0),
+            false => self.n_reduce,
+        };
+        let mut intermediate = None;
+        if !self.map_only && sample_inputs > 0 && !map_tasks.is_empty() {
+            let mut estimate = Estimate::default();
+            for index in sample_indices(map_tasks.len(), sample_inputs) {
+                let task = &map_tasks[index];
+                let map = self.input_maps.get(&task.id).unwrap_or(&self.map);
+                let input = BufReader::new(File::open(&task.input)?);
+                let partitions =
+                    catch_unwind(AssertUnwindSafe(|| map(input))).map_err(|panic| {
+                        io::Error::other(format!(
+                            "sampling {}: {}",
+                            task.input.display(),
+                            panic_message(panic)
+                        ))
+                    })?;
+                n_reduce = n_reduce.or(Some(partitions.len()));
+                estimate.sampled_inputs += 1;
+                estimate.sampled_bytes += task.bytes;
+                estimate.sampled_output_bytes +=
+                    partitions.iter().map(|p| p.len() as u64).sum::<u64>();
+            }
+            let input_bytes = map_tasks.iter().map(|task| task.bytes).sum();
+            intermediate = Some(estimate.extrapolate(map_tasks.len(), input_bytes));
+        }
+
+        Ok(Plan {
+            working_directory: self.working_directory.clone(),
+            map_tasks,
+            n_reduce,
+            split_points: self.split_points.clone(),
+            intermediate,
+            in_memory_shuffle: self.shuffles_in_memory() && !self.map_only,
+            merge_output: self.merge_output,
+        })
+    }
+
+    /// The map jobs still to run, built as they are dispatched. Every task
+    /// is on the status board, so progress is known from the start.
+    fn map_jobs(&self) -> impl Iterator<Item = Job> + '_ {
+        let recovered = self.recovered.lock().unwrap();
+        let ids = (1..=self.input_files.len() as i32)
+            .filter(|&job_id| {
+                !self.skip_committed(&recovered, TaskId::Map(job_id))
+                    && !self.restore_cached(job_id)
+            })
+            .collect::<Vec<i32>>();
+        for &job_id in &ids {
+            self.status.task_idle(TaskId::Map(job_id));
+        }
+
+        ids.into_iter().map(move |job_id| {
+            let input = self.input_files[job_id as usize - 1].clone();
+            if self.map_only {
+                Job::MapOnly((job_id, input))
+            } else {
+                Job::Map((job_id, input))
+            }
+        })
+    }
+
+    /// Marks a task recovered from the log as done. Partial outputs of a
+    /// recovered task that was not committed are removed so the reduce
+    /// phase does not pick them up.
+    fn skip_committed(&self, recovered: &Option<Recovered>, id: TaskId) -> bool {
+        // A previous run's in-memory map output is lost.
+        let lost = self.shuffles_in_memory() && !self.map_only && matches!(id, TaskId::Map(_));
+        match recovered {
+            Some(recovered) if recovered.is_committed(id) && !lost => {
+                self.status.task_done(id);
+                true
+            }
+            Some(_) => {
+                for (name, _) in committed_outputs(&self.working_directory, id) {
+                    let _ = remove_file(self.working_directory.join(name));
+                }
+                false
+            }
+            None => false,
         }
     }
 
+    fn map_cache(&self) -> Option<&MapCache> {
+        self.map_cache
+            .as_ref()
+            .filter(|_| !self.map_only && !self.shuffles_in_memory())
+    }
+
+    /// Takes a map task's outputs from the cache instead of running it.
+    fn restore_cached(&self, job_id: i32) -> bool {
+        let Some(cache) = self.map_cache() else {
+            return false;
+        };
+        let Ok(fingerprint) = Fingerprint::of(&self.input_files[job_id as usize - 1]) else {
+            return false;
+        };
+        let id = TaskId::Map(job_id);
+        match cache.restore(&fingerprint, job_id, &self.working_directory) {
+            Ok(true) => {
+                self.log(Entry::Done(
+                    id,
+                    checksummed_outputs(&self.working_directory, id),
+                ));
+                self.status.task_done(id);
+                true
+            }
+            _ => {
+                self.fingerprints
+                    .lock()
+                    .unwrap()
+                    .insert(job_id, fingerprint);
+                false
+            }
+        }
+    }
+
+    fn cache_outputs(&self, job_id: i32) {
+        let fingerprint = self.fingerprints.lock().unwrap().remove(&job_id);
+        if let (Some(cache), Some(fingerprint)) = (self.map_cache(), fingerprint) {
+            // Failing to cache only costs the next run a map task.
+            let _ = cache.store(&fingerprint, job_id, &self.working_directory);
+        }
+    }
+
+    fn dispatch(&self, transport: &mut dyn Transport, job: Job) {
+        let id = job.task_id();
+        let mut outstanding = self.outstanding.lock().unwrap();
+        let attempt = outstanding.get(&id).map_or(1, |task| task.attempt + 1);
+        let deadline = self.task_timeout.map(|timeout| transport.now() + timeout);
+        outstanding.insert(
+            id,
+            Outstanding {
+                job: job.clone(),
+                attempt,
+                deadline,
+            },
+        );
+        drop(outstanding);
+
+        self.status.task_idle(id);
+        self.log(Entry::Dispatched(id));
+        self.trace(
+            id,
+            match attempt {
+                1 => EventKind::Dispatched { attempt },
+                _ => EventKind::Retried { attempt },
+            },
+        );
+        transport.send(job);
+    }
+
+    /// Sends a failed task out again if it has attempts left.
+    fn retry(&self, transport: &mut dyn Transport, id: TaskId) -> bool {
+        let job = match self.outstanding.lock().unwrap().get(&id) {
+            Some(task) if task.attempt < self.max_attempts => task.job.clone(),
+            _ => return false,
+        };
+        self.dispatch(transport, job);
+        true
+    }
+
+    fn trace(&self, id: TaskId, kind: EventKind) {
+        if let Some(tracer) = &self.tracer {
+            tracer.emit(id, kind);
+        }
+    }
+
+    /// Appends `entry` to the write-ahead log. A job whose progress can
+    /// no longer be logged fails rather than run on unable to resume.
+    fn log(&self, entry: Entry) {
+        if let Err(e) = self.wal.record(&entry) {
+            self.fail(&format!("write-ahead log: {}", e));
+        }
+    }
+
+    fn sync_log(&self) {
+        if let Err(e) = self.wal.sync() {
+            self.fail(&format!("write-ahead log: {}", e));
+        }
+    }
+
+    /// Stops dispatching, as a cancel does, and ends the job failed.
+    fn fail(&self, error: &str) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.status.fail(error);
+    }
+
+    /// The map output files in the working directory, by reduce task.
+    fn map_output_files(&self) -> HashMap<i32, Vec<PathBuf>> {
+        match read_dir(self.working_directory.clone()) {
+            Ok(entries) => {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .fold(HashMap::new(), |mut grouped, entry| {
+                        let _ = entry
+                            .file_name()
+                            .into_string()
+                            .and_then(|filename| {
+                                filename
+                                    .split('.')
+                                    .nth(3)
+                                    .and_then(|i| i.parse().ok())
+                                    .ok_or(entry.file_name())
+                            })
+                            .map(|key| {
+                                let files = grouped.entry(key).or_insert(vec![]);
+                                files.push(entry.path())
+                            });
+                        grouped
+                    })
+            }
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    fn reduce_jobs(&self) -> impl Iterator<Item = Job> {
+        let groups = match self.shuffles_in_memory() {
+            true => self
+                .memory_shuffle
+                .partitions()
+                .into_iter()
+                .map(|index| (index, vec![]))
+                .collect(),
+            false => self.map_output_files(),
+        };
+
+        let recovered = self.recovered.lock().unwrap();
+        let mut groups = groups
+            .into_iter()
+            .filter(|(index, _)| !self.skip_committed(&recovered, TaskId::Reduce(*index)))
+            .collect::<Vec<(i32, Vec<PathBuf>)>>();
+        groups.sort();
+        for (index, _) in &groups {
+            self.status.task_idle(TaskId::Reduce(*index));
+        }
+        groups.into_iter().map(Job::Reduce)
+    }
+
+    /// Runs the job to completion on `n_workers` worker threads, which
+    /// have all exited by the time it returns. A master may be run again.
+    /// Panics if a worker thread panicked.
     pub fn run(&self, n_workers: i32) -> Vec<PathBuf> {
-        self.spawn_workers(n_workers);
-
-        let n_map = self.do_map();
-        self.wait_for_completion(n_map);
-        let n_reduce = self.do_reduce();
-        self.wait_for_completion(n_reduce);
-
-        self.argument_result_files()
-    }
-
-    fn spawn_workers(&self, n_workers: i32) {
-        for _ in 0..n_workers {
-            let working_directory = self.working_directory.clone();
-            let map = self.map.clone();
-            let reduce = self.reduce.clone();
-            let job_queue = self.worker_job_queue.clone();
-            let results_queue = self.worker_results_queue.clone();
-
-            thread::spawn(move || {
-                let worker = Worker {
-                    working_directory,
-                    map,
-                    reduce,
-                    job_queue,
-                    results_queue,
+        // Workers take jobs from a queue that holds at most this many, and
+        // no more than twice this many tasks are ever outstanding.
+        let depth = n_workers.max(1) as usize;
+        let (worker_results_queue, results_queue) = chan::r#async();
+        let worker = Arc::new(self.worker());
+
+        let (result_files, workers) = match self.dispatch {
+            Dispatch::SharedQueue => {
+                let (job_queue, worker_job_queue) = chan::sync(depth);
+                let workers = spawn_workers(n_workers, |id| {
+                    let worker = worker.clone();
+                    let job_queue = worker_job_queue.clone();
+                    let results_queue = worker_results_queue.clone();
+                    move || worker.run(id, job_queue, results_queue)
+                });
+                let mut transport = Channels {
+                    send: &|job| job_queue.send(job),
+                    results_queue: &results_queue,
+                    started: Instant::now(),
+                };
+                let result_files = self.drive(&mut transport, 2 * depth);
+                // Closing the queue lets the workers finish.
+                drop(job_queue);
+                (result_files, workers)
+            }
+            Dispatch::WorkStealing => {
+                let deques = Arc::new(Deques::new(depth));
+                let workers = spawn_workers(n_workers, |id| {
+                    let worker = worker.clone();
+                    let deques = deques.clone();
+                    let results_queue = worker_results_queue.clone();
+                    move || {
+                        while let Some(job) = deques.pop(id) {
+                            results_queue.send(worker.process(id, job));
+                        }
+                    }
+                });
+                let mut transport = Channels {
+                    send: &|job| deques.push(job),
+                    results_queue: &results_queue,
+                    started: Instant::now(),
                 };
-                worker.run()
+                let result_files = self.drive(&mut transport, 2 * depth);
+                deques.close();
+                (result_files, workers)
+            }
+        };
+
+        let panics = workers
+            .into_iter()
+            .filter_map(|worker| worker.join().err())
+            .collect::<Vec<_>>();
+        if let Some(panic) = panics.into_iter().next() {
+            resume_unwind(panic);
+        }
+        result_files
+    }
+
+    /// Runs both phases over `transport`, with at most `max_outstanding`
+    /// tasks sent but not finished.
+    pub(crate) fn drive(
+        &self,
+        transport: &mut dyn Transport,
+        max_outstanding: usize,
+    ) -> Vec<PathBuf> {
+        self.status.set_phase(Phase::Pending);
+        self.outstanding.lock().unwrap().clear();
+        self.fingerprints.lock().unwrap().clear();
+        let opened = match self.recovered.lock().unwrap().as_ref() {
+            Some(_) => self.wal.reopen(),
+            None => self.wal.create(&self.input_files),
+        };
+        if let Err(e) = opened {
+            self.fail(&format!("write-ahead log: {}", e));
+        }
+
+        if self.status.phase() != Phase::Failed {
+            self.status.set_phase(Phase::Map);
+            self.run_phase(self.map_jobs(), transport, max_outstanding);
+            self.sync_log();
+        }
+        if !self.map_only && !self.is_cancelled() {
+            self.status.set_phase(Phase::Reduce);
+            self.run_phase(self.reduce_jobs(), transport, max_outstanding);
+            self.sync_log();
+        }
+
+        if self.status.phase() != Phase::Failed {
+            self.status.set_phase(match self.is_cancelled() {
+                true => Phase::Cancelled,
+                false => Phase::Done,
             });
         }
+        self.recovered.lock().unwrap().take();
+        self.memory_shuffle.clear();
+
+        let result_files = self.argument_result_files();
+        if !self.merge_output {
+            return result_files;
+        }
+        // A merged file missing results must not pass for a complete one.
+        let merged = self.working_directory.join(MERGED_NAME);
+        let _ = remove_file(&merged);
+        let failed = self
+            .status
+            .snapshot()
+            .tasks
+            .iter()
+            .any(|task| task.state == TaskState::Failed);
+        if failed || self.is_cancelled() {
+            return vec![];
+        }
+        match merge_results(result_files, &merged) {
+            Ok(()) => vec![merged],
+            Err(e) => {
+                self.fail(&format!("merge results: {}", e));
+                vec![]
+            }
+        }
+    }
+
+    /// Runs the job on another thread and returns a handle to it.
+    pub fn start(self, n_workers: i32) -> JobHandle {
+        JobHandle::spawn(self, move |master| master.run(n_workers))
+    }
+
+    /// A worker running this master's tasks.
+    pub(crate) fn worker(&self) -> Worker {
+        Worker {
+            working_directory: self.working_directory.clone(),
+            map: self.map.clone(),
+            input_maps: self.input_maps.clone(),
+            reduce: self.reduce.clone(),
+            status: self.status.clone(),
+            output_format: self.output_format,
+            execution: self.execution,
+            shuffle: self
+                .shuffles_in_memory()
+                .then(|| self.memory_shuffle.clone()),
+            cancelled: self.cancelled.clone(),
+            tracer: self.tracer.clone(),
+            retry: self.io_retry,
+        }
     }
 
-    fn wait_for_completion(&self, n_jobs: i32) {
-        let mut n_complete = 0;
-        while n_complete < n_jobs {
-            self.results_queue.recv().map(|_| n_complete += 1);
+    /// Dispatches `jobs` while keeping at most `max_outstanding` of them
+    /// queued or running, and waits for each to finish, retrying failures
+    /// and timeouts. Stops dispatching once the job is cancelled.
+    fn run_phase<I: Iterator<Item = Job>>(
+        &self,
+        mut jobs: I,
+        transport: &mut dyn Transport,
+        max_outstanding: usize,
+    ) {
+        loop {
+            while self.n_outstanding() < max_outstanding && !self.is_cancelled() {
+                match jobs.next() {
+                    Some(job) => self.dispatch(transport, job),
+                    None => break,
+                }
+            }
+            if self.n_outstanding() == 0 {
+                break;
+            }
+
+            let (id, error) = match transport.recv(self.next_deadline()) {
+                Received::Result(JobResult::MapFinished(id)) => (TaskId::Map(id), None),
+                Received::Result(JobResult::ReduceFinished(id)) => (TaskId::Reduce(id), None),
+                Received::Result(JobResult::MapFailed(id, error)) => (TaskId::Map(id), Some(error)),
+                Received::Result(JobResult::ReduceFailed(id, error)) => {
+                    (TaskId::Reduce(id), Some(error))
+                }
+                Received::Result(JobResult::Cancelled(id)) => {
+                    self.outstanding.lock().unwrap().remove(&id);
+                    continue;
+                }
+                Received::TimedOut => {
+                    self.expire(transport);
+                    continue;
+                }
+                Received::Closed => break,
+            };
+            // A late or duplicate result for a task that already finished.
+            if !self.outstanding.lock().unwrap().contains_key(&id) {
+                continue;
+            }
+            match error {
+                None => {
+                    let outputs = checksummed_outputs(&self.working_directory, id);
+                    self.log(Entry::Done(id, outputs));
+                    self.status.task_done(id);
+                    if let TaskId::Map(job_id) = id {
+                        self.cache_outputs(job_id);
+                    }
+                }
+                Some(error) => {
+                    self.status.task_failed(id, &error);
+                    if !self.is_cancelled() && self.retry(transport, id) {
+                        continue;
+                    }
+                    self.log(Entry::Failed(id));
+                }
+            }
+            self.outstanding.lock().unwrap().remove(&id);
         }
     }
 
+    fn n_outstanding(&self) -> usize {
+        self.outstanding.lock().unwrap().len()
+    }
+
+    fn next_deadline(&self) -> Option<Duration> {
+        let outstanding = self.outstanding.lock().unwrap();
+        outstanding.values().filter_map(|task| task.deadline).min()
+    }
+
+    /// Retries or fails the tasks whose deadline has passed.
+    fn expire(&self, transport: &mut dyn Transport) {
+        let now = transport.now();
+        let expired = self
+            .outstanding
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, task)| task.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<TaskId>>();
+        for id in expired {
+            self.status.task_failed(id, "task timed out");
+            self.trace(
+                id,
+                EventKind::Failed {
+                    worker: None,
+                    duration_us: self.task_timeout.map_or(0, micros),
+                    error: "task timed out".to_string(),
+                },
+            );
+            if self.is_cancelled() || !self.retry(transport, id) {
+                self.log(Entry::Failed(id));
+                self.outstanding.lock().unwrap().remove(&id);
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
     fn argument_result_files(&self) -> Vec<PathBuf> {
         read_dir(self.working_directory.clone())
             .map(|entries| {
@@ -131,7 +830,7 @@ impl Master {
                     .filter_map(|entry| entry.ok())
                     .filter_map(|entry| {
                         entry.file_name().into_string().ok().and_then(|name| {
-                            match name.split(".").last() {
+                            match name.split('.').next_back() {
                                 Some("result") => Some(entry),
                                 _ => None,
                             }
@@ -140,7 +839,60 @@ impl Master {
                     .map(|entry| entry.path())
                     .collect()
             })
-            .unwrap_or(vec![])
+            .unwrap_or_default()
+    }
+}
+
+fn spawn_workers<F, W>(n_workers: i32, worker: F) -> Vec<JoinHandle<()>>
+where
+    F: Fn(usize) -> W,
+    W: FnOnce() + Send + 'static,
+{
+    (0..n_workers as usize)
+        .map(|id| thread::spawn(worker(id)))
+        .collect()
+}
+
+/// A job started with `Master::start`.
+pub struct JobHandle {
+    status: JobStatus,
+    cancelled: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<PathBuf>>,
+}
+
+impl JobHandle {
+    pub(crate) fn spawn<F>(master: Master, run: F) -> Self
+    where
+        F: FnOnce(&Master) -> Vec<PathBuf> + Send + 'static,
+    {
+        JobHandle {
+            status: master.status.clone(),
+            cancelled: master.cancelled.clone(),
+            thread: thread::spawn(move || run(&master)),
+        }
+    }
+
+    /// Stops dispatching tasks. Running tasks finish, queued ones are
+    /// abandoned, and the reduce phase is skipped. A cancelled job can be
+    /// picked up again with `Master::resume`.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn progress(&self) -> Snapshot {
+        self.status.snapshot()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the job and returns its result files. Panics if the job
+    /// panicked.
+    pub fn wait(self) -> Vec<PathBuf> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| resume_unwind(panic))
     }
 }
 
@@ -148,18 +900,25 @@ impl Master {
 mod tests {
     use std::{
         collections::HashSet,
-        fs::{remove_file, OpenOptions},
+        fs::{create_dir_all, read_to_string, remove_dir, write, OpenOptions},
         hash::Hash,
-        io::BufRead,
+        io::{BufRead, Read, Write},
+        net::TcpStream,
+        sync::atomic::{AtomicUsize, Ordering},
     };
 
     use super::*;
+    use crate::{
+        checkpoint::WAL_NAME, isolation::Limits, keyed::Keyed, pipeline::remove_job_outputs,
+        trace::Event, worker::KeyValue,
+    };
 
     fn map_fn(_input: BufReader<File>) -> Vec<String> {
-        vec!["1", "2", "3", "4"]
-            .iter()
-            .map(|s| s.to_string())
-            .collect()
+        map_fn_results()
+    }
+
+    fn map_fn_results() -> Vec<String> {
+        ["1", "2", "3", "4"].iter().map(|s| s.to_string()).collect()
     }
 
     fn reduce_fn(_input: Vec<BufReader<File>>) -> String {
@@ -169,7 +928,7 @@ mod tests {
     #[test]
     fn master_enqueues_map_jobs() {
         let working_directory = PathBuf::from("./test-data/master_enqueues_map_jobs");
-        let input_files = vec!["input_1", "input_2", "input_3", "input_4"]
+        let input_files = ["input_1", "input_2", "input_3", "input_4"]
             .into_iter()
             .map(|filename| {
                 let mut path = working_directory.clone();
@@ -183,11 +942,7 @@ mod tests {
             Arc::new(map_fn),
             Arc::new(reduce_fn),
         );
-        let job_recv = master.worker_job_queue.clone();
-        let map_jobs = thread::spawn(move || job_recv.iter().collect::<Vec<Job>>());
-
-        let n_map_jobs = master.do_map();
-        drop(master);
+        let map_jobs = master.map_jobs().collect::<Vec<Job>>();
 
         let expected_jobs = input_files
             .iter()
@@ -195,8 +950,8 @@ mod tests {
             .map(|(i, f)| Job::Map(((i + 1) as i32, f.clone())))
             .collect::<Vec<Job>>();
 
-        assert_eq!(n_map_jobs, 4);
-        assert_eq!(map_jobs.join().unwrap(), expected_jobs);
+        assert_eq!(map_jobs, expected_jobs);
+        assert_eq!(master.status().snapshot().counters.idle, 4);
     }
 
     fn vec_eq<T>(a: &[T], b: &[T]) -> bool
@@ -212,7 +967,7 @@ mod tests {
     #[test]
     fn run_map_reduce() {
         let working_directory = PathBuf::from("./test-data/master_runs_map_reduce");
-        let input_files = vec!["input_1", "input_2", "input_3", "input_4"]
+        let input_files = ["input_1", "input_2", "input_3", "input_4"]
             .into_iter()
             .map(|filename| {
                 let mut path = working_directory.clone();
@@ -229,7 +984,7 @@ mod tests {
 
         let result_files = master.run(2);
 
-        let expected_files = vec![
+        let expected_files = [
             "reduce.1.result",
             "reduce.2.result",
             "reduce.3.result",
@@ -267,5 +1022,548 @@ mod tests {
             result_file.push(format!("reduce.{}.result", i));
             let _ = remove_file(result_file);
         }
+        let _ = remove_file(working_directory.join(WAL_NAME));
+    }
+
+    fn failing_map_fn(input: BufReader<File>) -> Vec<String> {
+        if input.lines().any(|l| l.unwrap_or_default() == "boom") {
+            panic!("boom");
+        }
+        map_fn_results()
+    }
+
+    #[test]
+    fn master_serves_job_status() {
+        let working_directory = PathBuf::from("./test-data/master_serves_job_status");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| {
+                let mut path = working_directory.clone();
+                path.push(filename);
+                path
+            })
+            .collect::<Vec<PathBuf>>();
+        let mut master = Master::new(
+            working_directory.clone(),
+            input_files,
+            Arc::new(failing_map_fn),
+            Arc::new(reduce_fn),
+        );
+        let addr = master.serve_status("127.0.0.1:0").unwrap();
+
+        master.run(2);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /status.json HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.contains("\"phase\":\"done\""));
+        assert!(response.contains("\"kind\":\"map\",\"id\":2,\"state\":\"failed\""));
+        let counters = master.status().snapshot().counters;
+        assert_eq!(counters.map_tasks, 2);
+        assert_eq!(counters.reduce_tasks, 4);
+        assert_eq!(counters.failed, 1);
+        assert_eq!(counters.done, 5);
+
+        for i in 1..(4 + 1) {
+            let mut map_file = working_directory.clone();
+            map_file.push(format!("map.1.reduce.{}", i));
+            let _ = remove_file(map_file);
+            let mut result_file = working_directory.clone();
+            result_file.push(format!("reduce.{}.result", i));
+            let _ = remove_file(result_file);
+        }
+        let _ = remove_file(working_directory.join(WAL_NAME));
+    }
+
+    #[test]
+    fn master_fails_when_log_cannot_be_written() {
+        let working_directory = PathBuf::from("./test-data/master_fails_without_log");
+        let wal = working_directory.join(WAL_NAME);
+        // A directory where the log belongs cannot be opened as a file.
+        create_dir_all(&wal).unwrap();
+        let map_calls = Arc::new(AtomicUsize::new(0));
+        let calls = map_calls.clone();
+        let master = Master::new(
+            working_directory.clone(),
+            vec![working_directory.join("input_1")],
+            Arc::new(move |input| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                map_fn(input)
+            }),
+            Arc::new(reduce_fn),
+        );
+
+        let result_files = master.run(1);
+        let snapshot = master.status().snapshot();
+        assert_eq!(snapshot.phase, Phase::Failed);
+        assert!(snapshot.error.unwrap().starts_with("write-ahead log: "));
+        assert_eq!(map_calls.load(Ordering::SeqCst), 0);
+        assert!(result_files.is_empty());
+        remove_dir(wal).unwrap();
+    }
+
+    #[test]
+    fn master_resumes_from_checkpoint() {
+        let working_directory = PathBuf::from("./test-data/master_resumes_from_checkpoint");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let wal = working_directory.join(WAL_NAME);
+
+        Master::new(
+            working_directory.clone(),
+            input_files,
+            Arc::new(map_fn),
+            Arc::new(reduce_fn),
+        )
+        .run(2);
+
+        // Pretend the master died right after map 1 was committed.
+        let crashed = read_to_string(&wal)
+            .unwrap()
+            .lines()
+            .filter(|l| l.starts_with("input\t") || l.starts_with("done\tmap\t1\t"))
+            .map(|l| format!("{}\n", l))
+            .collect::<String>();
+        write(&wal, &crashed).unwrap();
+
+        let resume = |map_calls: Arc<AtomicUsize>| {
+            let master = Master::resume(
+                working_directory.clone(),
+                Arc::new(move |input| {
+                    map_calls.fetch_add(1, Ordering::SeqCst);
+                    map_fn(input)
+                }),
+                Arc::new(reduce_fn),
+            )
+            .unwrap();
+            master.run(2)
+        };
+
+        let map_calls = Arc::new(AtomicUsize::new(0));
+        let result_files = resume(map_calls.clone());
+        assert_eq!(map_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(result_files.len(), 4);
+
+        // Everything is committed now, so resuming again does no work.
+        let map_calls = Arc::new(AtomicUsize::new(0));
+        resume(map_calls.clone());
+        assert_eq!(map_calls.load(Ordering::SeqCst), 0);
+
+        // A committed output that changed on disk fails verification, even
+        // at the same length.
+        write(working_directory.join("map.1.reduce.3"), "7").unwrap();
+        let map_calls = Arc::new(AtomicUsize::new(0));
+        resume(map_calls.clone());
+        assert_eq!(map_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            read_to_string(working_directory.join("map.1.reduce.3")).unwrap(),
+            "3"
+        );
+
+        for i in 1..(4 + 1) {
+            for j in 1..(2 + 1) {
+                let _ = remove_file(working_directory.join(format!("map.{}.reduce.{}", j, i)));
+            }
+            let _ = remove_file(working_directory.join(format!("reduce.{}.result", i)));
+        }
+        let _ = remove_file(wal);
+    }
+
+    #[test]
+    fn master_runs_map_only_job() {
+        let working_directory = PathBuf::from("./test-data/master_runs_map_only_job");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let keyed = Keyed::new(0);
+        let mut master = Master::map_only(
+            working_directory.clone(),
+            input_files,
+            keyed.map(Arc::new(|contents: String| {
+                contents
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("keep "))
+                    .map(|kept| KeyValue {
+                        key: kept.to_string(),
+                        value: "kept".to_string(),
+                    })
+                    .collect()
+            })),
+        );
+        master.set_output_format(OutputFormat::JsonLines);
+
+        let mut result_files = master.run(2);
+        result_files.sort();
+
+        assert_eq!(
+            result_files,
+            vec![
+                working_directory.join("map.1.result"),
+                working_directory.join("map.2.result"),
+            ]
+        );
+        assert_eq!(
+            read_to_string(&result_files[0]).unwrap(),
+            "{\"key\":\"a\",\"value\":\"kept\"}\n{\"key\":\"c\",\"value\":\"kept\"}\n"
+        );
+        assert_eq!(
+            read_to_string(&result_files[1]).unwrap(),
+            "{\"key\":\"e\",\"value\":\"kept\"}\n"
+        );
+        assert_eq!(master.status().snapshot().counters.reduce_tasks, 0);
+
+        remove_job_outputs(&working_directory).unwrap();
+        assert_eq!(read_dir(&working_directory).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn master_merges_range_partitioned_output() {
+        let working_directory = PathBuf::from("./test-data/master_merges_output");
+        let spec = JobSpec {
+            n_reduce: 3,
+            working_directory: working_directory.clone(),
+            merge_output: true,
+            ..JobSpec::new(
+                "terasort",
+                vec![
+                    working_directory.join("input_1"),
+                    working_directory.join("input_2"),
+                ],
+            )
+            .unwrap()
+        };
+        let master = Master::from_spec(&spec).unwrap();
+
+        let result_files = master.run(2);
+        let merged = working_directory.join(MERGED_NAME);
+        assert_eq!(result_files, vec![merged.clone()]);
+        let keys = read_to_string(&merged)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect::<Vec<String>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys.len(), 8);
+        assert_eq!(keys, sorted);
+
+        remove_job_outputs(&working_directory).unwrap();
+        assert_eq!(read_dir(&working_directory).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn master_merges_only_complete_runs() {
+        let working_directory = PathBuf::from("./test-data/master_merge_needs_all_tasks");
+        let mut master = Master::new(
+            working_directory.clone(),
+            vec![
+                working_directory.join("input_1"),
+                working_directory.join("input_2"),
+            ],
+            Arc::new(failing_map_fn),
+            Arc::new(reduce_fn),
+        );
+        master.set_merge_output(true);
+        master.set_max_attempts(1);
+
+        assert_eq!(master.run(2), Vec::<PathBuf>::new());
+        assert!(!working_directory.join(MERGED_NAME).exists());
+        assert_eq!(master.status().snapshot().counters.failed, 1);
+
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    // Creates a missing input once a task has failed on it.
+    struct InputAfterFailure {
+        input: PathBuf,
+        failures: AtomicUsize,
+    }
+
+    impl Sink for InputAfterFailure {
+        fn record(&self, event: &Event) {
+            if let EventKind::Failed { .. } = event.kind {
+                self.failures.fetch_add(1, Ordering::SeqCst);
+                write(&self.input, "appeared late\n").unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn master_redispatches_task_that_failed() {
+        let working_directory = PathBuf::from("./test-data/master_redispatches_failed_task");
+        let input = working_directory.join("input_1");
+        let sink = Arc::new(InputAfterFailure {
+            input: input.clone(),
+            failures: AtomicUsize::new(0),
+        });
+        let mut master = Master::new(
+            working_directory.clone(),
+            vec![input.clone()],
+            Arc::new(map_fn),
+            Arc::new(reduce_fn),
+        );
+        master.set_trace_sink(sink.clone());
+
+        let result_files = master.run(1);
+
+        assert_eq!(sink.failures.load(Ordering::SeqCst), 1);
+        assert_eq!(result_files.len(), 4);
+        let counters = master.status().snapshot().counters;
+        assert_eq!(counters.failed, 0);
+        assert_eq!(counters.done, 5);
+
+        remove_job_outputs(&working_directory).unwrap();
+        remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn master_retries_isolated_tasks() {
+        let working_directory = PathBuf::from("./test-data/master_retries_isolated_tasks");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let crashed = working_directory.join("crashed");
+        let marker = crashed.clone();
+        let mut master = Master::new(
+            working_directory.clone(),
+            input_files,
+            Arc::new(move |input: BufReader<File>| {
+                // The first attempt takes its whole process down.
+                if input.lines().any(|l| l.unwrap_or_default() == "crash once") && !marker.exists()
+                {
+                    write(&marker, "").unwrap();
+                    std::process::abort();
+                }
+                map_fn_results()
+            }),
+            Arc::new(reduce_fn),
+        );
+        master.set_execution(Execution::Process(Limits::default()));
+
+        let result_files = master.run(2);
+
+        assert!(crashed.exists());
+        assert_eq!(result_files.len(), 4);
+        let counters = master.status().snapshot().counters;
+        assert_eq!(counters.failed, 0);
+        assert_eq!(counters.done, 6);
+
+        // A task that keeps crashing fails once its attempts run out.
+        let mut master = Master::new(
+            working_directory.clone(),
+            vec![working_directory.join("input_1")],
+            Arc::new(|_| std::process::abort()),
+            Arc::new(reduce_fn),
+        );
+        master.set_execution(Execution::Process(Limits::default()));
+        master.set_max_attempts(2);
+        master.run(1);
+
+        let snapshot = master.status().snapshot();
+        assert_eq!(snapshot.tasks[0].state, TaskState::Failed);
+        assert_eq!(
+            snapshot.tasks[0].error.as_deref(),
+            Some("task process aborted, possibly out of memory")
+        );
+
+        remove_job_outputs(&working_directory).unwrap();
+        let _ = remove_file(crashed);
+    }
+
+    #[test]
+    fn master_joins_workers_and_runs_again() {
+        let working_directory = PathBuf::from("./test-data/master_reruns_job");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let map: MapFn = Arc::new(map_fn);
+        let master = Master::new(
+            working_directory.clone(),
+            input_files,
+            map.clone(),
+            Arc::new(reduce_fn),
+        );
+
+        for _ in 0..2 {
+            assert_eq!(master.run(3).len(), 4);
+            // Only we and the master still hold the map function.
+            assert_eq!(Arc::strong_count(&map), 2);
+            assert_eq!(master.status().snapshot().counters.done, 6);
+        }
+        drop(master);
+        assert_eq!(Arc::strong_count(&map), 1);
+
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn master_cancels_started_job() {
+        let working_directory = PathBuf::from("./test-data/master_cancels_job");
+        let input_files = ["input_1", "input_2", "input_3", "input_4"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let map_calls = Arc::new(AtomicUsize::new(0));
+        let (started_send, started) = chan::r#async();
+        let (release, release_recv) = chan::r#async::<()>();
+        let calls = map_calls.clone();
+        let master = Master::new(
+            working_directory.clone(),
+            input_files,
+            Arc::new(move |input| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                started_send.send(());
+                release_recv.recv();
+                map_fn(input)
+            }),
+            Arc::new(reduce_fn),
+        );
+
+        let job = master.start(1);
+        started.recv().unwrap();
+        let counters = job.progress().counters;
+        assert_eq!((counters.map_tasks, counters.in_progress), (4, 1));
+
+        job.cancel();
+        drop(release);
+        assert!(job.wait().is_empty());
+        assert_eq!(map_calls.load(Ordering::SeqCst), 1);
+
+        // The cancelled job picks up where it stopped.
+        let calls = map_calls.clone();
+        let master = Master::resume(
+            working_directory.clone(),
+            Arc::new(move |input| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                map_fn(input)
+            }),
+            Arc::new(reduce_fn),
+        )
+        .unwrap();
+        let job = master.start(2);
+        assert_eq!(job.wait().len(), 4);
+        assert_eq!(map_calls.load(Ordering::SeqCst), 4);
+
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn master_runs_with_work_stealing() {
+        let working_directory = PathBuf::from("./test-data/master_steals_work");
+        let input_files = ["input_1", "input_2", "input_3", "input_4"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let mut master = Master::new(
+            working_directory.clone(),
+            input_files,
+            Arc::new(map_fn),
+            Arc::new(reduce_fn),
+        );
+        master.set_dispatch(Dispatch::WorkStealing);
+
+        let result_files = master.run(3);
+
+        assert_eq!(result_files.len(), 4);
+        for path in result_files {
+            assert_eq!(read_to_string(path).unwrap(), "1234");
+        }
+        assert_eq!(master.status().snapshot().counters.done, 8);
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn master_shuffles_in_memory() {
+        let working_directory = PathBuf::from("./test-data/master_shuffles_in_memory");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let keyed = Keyed::new(3);
+        let word_count = |shuffle| {
+            let mut master = Master::new(
+                working_directory.clone(),
+                input_files.clone(),
+                keyed.map(Arc::new(|contents: String| {
+                    contents
+                        .split_whitespace()
+                        .map(|word| KeyValue {
+                            key: word.to_string(),
+                            value: "1".to_string(),
+                        })
+                        .collect()
+                })),
+                keyed.reduce(Arc::new(|key, values| {
+                    vec![KeyValue {
+                        key: key.to_string(),
+                        value: values.count().to_string(),
+                    }]
+                })),
+            );
+            master.set_shuffle(shuffle);
+            let mut results = master
+                .run(2)
+                .iter()
+                .map(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                    (name, read_to_string(path).unwrap())
+                })
+                .collect::<Vec<(String, String)>>();
+            results.sort();
+            results
+        };
+
+        let in_memory = word_count(Shuffle::Memory);
+        // Only the inputs, the log and the results are on disk.
+        assert_eq!(
+            read_dir(&working_directory).unwrap().count(),
+            3 + in_memory.len()
+        );
+        remove_job_outputs(&working_directory).unwrap();
+
+        assert_eq!(in_memory, word_count(Shuffle::Disk));
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn master_retries_tasks_that_time_out() {
+        let working_directory = PathBuf::from("./test-data/master_times_out_tasks");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let map_attempts = attempts.clone();
+        let mut master = Master::new(
+            working_directory.clone(),
+            vec![working_directory.join("input_1")],
+            Arc::new(move |input| {
+                // The first attempt straggles.
+                if map_attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    thread::sleep(Duration::from_millis(500));
+                }
+                map_fn(input)
+            }),
+            Arc::new(reduce_fn),
+        );
+        master.set_task_timeout(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let result_files = master.run(2);
+
+        assert_eq!(result_files.len(), 4);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(master
+            .status()
+            .snapshot()
+            .tasks
+            .iter()
+            .all(|task| task.state == TaskState::Done));
+        // The run still waits for the straggler to exit.
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        remove_job_outputs(&working_directory).unwrap();
     }
 }
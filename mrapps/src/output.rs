@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{record::decode, worker::write_atomically};
+
+/// The file a job's results are merged into, in its working directory.
+pub const MERGED_NAME: &str = "job.output";
+
+/// How a job writes its final result files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Task output is written as-is.
+    #[default]
+    Text,
+    /// One JSON object per line: `{"key":..,"value":..}` for lines holding
+    /// an encoded record and `{"line":..}` for anything else.
+    JsonLines,
+}
+
+impl OutputFormat {
+    pub fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        match self {
+            OutputFormat::Text => f.write_all(contents.as_bytes()),
+            OutputFormat::JsonLines => {
+                for line in contents.lines() {
+                    writeln!(f, "{}", json_line(line))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The partition a `reduce.<i>.result` or `map.<i>.result` file holds.
+pub fn partition_index(path: &Path) -> Option<i32> {
+    path.file_name()?.to_str()?.split('.').nth(1)?.parse().ok()
+}
+
+/// Replaces `merged` with the result files of a job, in partition order.
+pub fn merge_results(mut result_files: Vec<PathBuf>, merged: &Path) -> io::Result<()> {
+    result_files.sort_by_key(|path| partition_index(path));
+    write_atomically(merged, |path| concatenate(&result_files, path))
+}
+
+/// Writes the contents of `files`, in order, to `path`. A file that does
+/// not end its last line gets a newline, so lines never run together.
+pub fn concatenate(files: &[PathBuf], path: &Path) -> io::Result<()> {
+    let mut merged = io::BufWriter::new(File::create(path)?);
+    let mut buffer = vec![0; 64 * 1024];
+    for file in files {
+        let mut f = File::open(file)?;
+        let mut last = b'\n';
+        loop {
+            match f.read(&mut buffer)? {
+                0 => break,
+                n => {
+                    merged.write_all(&buffer[..n])?;
+                    last = buffer[n - 1];
+                }
+            }
+        }
+        if last != b'\n' {
+            merged.write_all(b"\n")?;
+        }
+    }
+    merged.flush()
+}
+
+/// Renders one line of task output as it appears in `JsonLines` output.
+pub fn json_line(line: &str) -> String {
+    match decode(line) {
+        Some(kv) => format!(
+            "{{\"key\":{},\"value\":{}}}",
+            json_string(&kv.key),
+            json_string(&kv.value)
+        ),
+        None => format!("{{\"line\":{}}}", json_string(line)),
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_file};
+
+    use super::*;
+
+    #[test]
+    fn json_lines_encodes_records_and_lines() {
+        let path = Path::new("./test-data/output_json_lines.result");
+
+        OutputFormat::JsonLines
+            .write(path, "k\\t1\tsaid \"hi\"\nplain line\n")
+            .unwrap();
+
+        assert_eq!(
+            read_to_string(path).unwrap(),
+            "{\"key\":\"k\\t1\",\"value\":\"said \\\"hi\\\"\"}\n{\"line\":\"plain line\"}\n"
+        );
+        let _ = remove_file(path);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{hash::Hasher, sync::Arc};
+
+use crate::cache::StableHasher;
+
+/// Picks the reduce partition, in `0..n_reduce`, for an intermediate key.
+pub type Partitioner = Arc<dyn Fn(&str, usize) -> usize + Send + Sync>;
+
+/// Names the hash `hash_partitioner` places keys by; changes whenever it
+/// would place them differently.
+pub const HASH_PARTITIONER: &str = "stable-fnv1a-1";
+
+/// Partitions by a hash of the key that is the same in every process and
+/// build, so map outputs cached or written by another build still line up.
+pub fn hash_partitioner() -> Partitioner {
+    Arc::new(|key, n_reduce| {
+        let mut hasher = StableHasher::default();
+        hasher.write(key.as_bytes());
+        (hasher.finish() % n_reduce as u64) as usize
+    })
+}
+
+/// Sends keys below `split_points[0]` to partition 0, keys from
+/// `split_points[0]` below `split_points[1]` to partition 1, and so on, so
+/// partitions hold contiguous, increasing key ranges. Needs
+/// `n_reduce - 1` sorted split points.
+pub fn range_partitioner(split_points: Vec<String>) -> Partitioner {
+    Arc::new(move |key, n_reduce| {
+        let index = split_points.partition_point(|split| split.as_str() <= key);
+        index.min(n_reduce - 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_partitioner_keeps_ranges_in_order() {
+        let partitioner = range_partitioner(vec!["g".to_string(), "p".to_string()]);
+
+        let partitions = ["a", "f", "g", "h", "o", "p", "z"]
+            .iter()
+            .map(|key| partitioner(key, 3))
+            .collect::<Vec<usize>>();
+
+        assert_eq!(partitions, vec![0, 0, 1, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn hash_partitioner_stays_in_range() {
+        let partitioner = hash_partitioner();
+
+        for key in ["a", "b", "c", "d", "e"] {
+            assert!(partitioner(key, 3) < 3);
+            assert_eq!(partitioner(key, 3), partitioner(key, 3));
+        }
+        // Pinned, so a change to the hash that moves keys shows up here.
+        assert_eq!(
+            ["a", "b", "c", "three"].map(|key| partitioner(key, 3)),
+            [2, 1, 0, 1]
+        );
+    }
+}
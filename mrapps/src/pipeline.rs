@@ -0,0 +1,386 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::{create_dir_all, read_dir, remove_file, File},
+    io::{self, BufReader},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use crate::{
+    checkpoint::WAL_NAME, master::Master, output::MERGED_NAME, status::TaskState,
+    worker::panic_message,
+};
+
+/// One MapReduce job in a pipeline. Its input is `input_files` followed by
+/// the result files of every stage in `depends_on`.
+pub struct Stage {
+    pub name: String,
+    pub working_directory: PathBuf,
+    pub input_files: Vec<PathBuf>,
+    pub depends_on: Vec<String>,
+    pub map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+    pub reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
+    pub n_workers: i32,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    DuplicateStage(String),
+    UnknownDependency {
+        stage: String,
+        dependency: String,
+    },
+    Cycle(Vec<String>),
+    /// A task of the stage failed or its master panicked. Stages that
+    /// depend on it are not run.
+    StageFailed {
+        stage: String,
+        error: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::DuplicateStage(name) => write!(f, "stage `{}` is defined twice", name),
+            PipelineError::UnknownDependency { stage, dependency } => write!(
+                f,
+                "stage `{}` depends on unknown stage `{}`",
+                stage, dependency
+            ),
+            PipelineError::Cycle(stages) => {
+                write!(f, "stages form a cycle: {}", stages.join(", "))
+            }
+            PipelineError::StageFailed { stage, error } => {
+                write!(f, "stage `{}` failed: {}", stage, error)
+            }
+            PipelineError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+impl From<io::Error> for PipelineError {
+    fn from(e: io::Error) -> Self {
+        PipelineError::Io(e)
+    }
+}
+
+/// A DAG of `Master` jobs. Stages run as soon as everything they depend on
+/// has finished, so independent stages run concurrently.
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Stage>) -> Result<Self, PipelineError> {
+        let mut names = HashSet::new();
+        for stage in &stages {
+            if !names.insert(stage.name.clone()) {
+                return Err(PipelineError::DuplicateStage(stage.name.clone()));
+            }
+        }
+        for stage in &stages {
+            if let Some(dependency) = stage.depends_on.iter().find(|d| !names.contains(*d)) {
+                return Err(PipelineError::UnknownDependency {
+                    stage: stage.name.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+
+        let pipeline = Pipeline { stages };
+        let unreachable = pipeline.unreachable_stages();
+        if !unreachable.is_empty() {
+            return Err(PipelineError::Cycle(unreachable));
+        }
+        Ok(pipeline)
+    }
+
+    /// Names of the stages that can never become ready.
+    fn unreachable_stages(&self) -> Vec<String> {
+        let mut done = HashSet::new();
+        loop {
+            let ready = self
+                .stages
+                .iter()
+                .filter(|s| !done.contains(&s.name))
+                .filter(|s| s.depends_on.iter().all(|d| done.contains(d)))
+                .map(|s| s.name.clone())
+                .collect::<Vec<String>>();
+            if ready.is_empty() {
+                break;
+            }
+            done.extend(ready);
+        }
+
+        self.stages
+            .iter()
+            .filter(|s| !done.contains(&s.name))
+            .map(|s| s.name.clone())
+            .collect()
+    }
+
+    /// Runs every stage and returns the result files of the final stages,
+    /// those nothing else depends on. Outputs of the other stages are
+    /// deleted once all of their downstream stages have finished.
+    ///
+    /// After the first failure no more stages start; the error is returned
+    /// once the stages already running have finished.
+    pub fn run(self) -> Result<HashMap<String, Vec<PathBuf>>, PipelineError> {
+        let mut consumers = HashMap::<String, usize>::new();
+        for stage in &self.stages {
+            for dependency in &stage.depends_on {
+                *consumers.entry(dependency.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let mut pending = self.stages;
+        let mut finished = HashMap::<String, Vec<PathBuf>>::new();
+        let mut directories = HashMap::<String, PathBuf>::new();
+        let mut running = 0;
+        let mut failure = None;
+        let (done_send, done_recv) = chan::r#async();
+
+        loop {
+            if failure.is_none() {
+                let (ready, waiting) = pending.into_iter().partition::<Vec<Stage>, _>(|stage| {
+                    stage.depends_on.iter().all(|d| finished.contains_key(d))
+                });
+                pending = waiting;
+
+                for stage in ready {
+                    if let Err(e) = create_dir_all(&stage.working_directory) {
+                        failure = Some(PipelineError::Io(e));
+                        break;
+                    }
+                    let mut input_files = stage.input_files.clone();
+                    for dependency in &stage.depends_on {
+                        input_files.extend(finished[dependency].iter().cloned());
+                    }
+                    directories.insert(stage.name.clone(), stage.working_directory.clone());
+
+                    let done = done_send.clone();
+                    running += 1;
+                    thread::spawn(move || {
+                        let name = stage.name.clone();
+                        let depends_on = stage.depends_on.clone();
+                        // Every stage reports back, even when its master panics.
+                        let outcome =
+                            catch_unwind(AssertUnwindSafe(|| run_stage(stage, input_files)))
+                                .map_err(panic_message)
+                                .and_then(|outcome| outcome)
+                                .map_err(|error| PipelineError::StageFailed {
+                                    stage: name.clone(),
+                                    error,
+                                });
+                        done.send((name, depends_on, outcome));
+                    });
+                }
+            }
+            if running == 0 {
+                break;
+            }
+
+            let (name, depends_on, outcome) = match done_recv.recv() {
+                Some(done) => done,
+                None => break,
+            };
+            running -= 1;
+            let results = match outcome {
+                Ok(results) => results,
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    continue;
+                }
+            };
+            finished.insert(name, results);
+
+            for dependency in depends_on {
+                let remaining = consumers.get_mut(&dependency).unwrap();
+                *remaining -= 1;
+                if *remaining == 0 {
+                    if let Err(e) = remove_job_outputs(&directories[&dependency]) {
+                        failure.get_or_insert(PipelineError::Io(e));
+                    }
+                }
+            }
+        }
+
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+        Ok(finished
+            .into_iter()
+            .filter(|(name, _)| !consumers.contains_key(name))
+            .collect())
+    }
+}
+
+// Runs one stage to completion. Any failed task fails the stage, so later
+// stages never read partial output.
+fn run_stage(stage: Stage, input_files: Vec<PathBuf>) -> Result<Vec<PathBuf>, String> {
+    let master = Master::new(
+        stage.working_directory,
+        input_files,
+        stage.map,
+        stage.reduce,
+    );
+    let mut results = master.run(stage.n_workers);
+    let snapshot = master.status().snapshot();
+    if let Some(error) = snapshot.error {
+        return Err(error);
+    }
+    if let Some(task) = snapshot
+        .tasks
+        .iter()
+        .find(|task| task.state == TaskState::Failed)
+    {
+        return Err(format!(
+            "{} {} failed: {}",
+            task.id.kind(),
+            task.id.index(),
+            task.error.as_deref().unwrap_or("unknown error")
+        ));
+    }
+    results.sort();
+    Ok(results)
+}
+
+/// Deletes the intermediate, result and log files a `Master` run left in
+/// `working_directory`, leaving anything else there alone.
+pub fn remove_job_outputs(working_directory: &Path) -> io::Result<()> {
+    for entry in read_dir(working_directory)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().unwrap_or_default();
+        let parts = name.split('.').collect::<Vec<&str>>();
+        let is_output = match parts.as_slice() {
+            ["map", _, "reduce", _] => true,
+            ["reduce", _, "result"] | ["map", _, "result"] => true,
+            _ => name == WAL_NAME || name == MERGED_NAME,
+        };
+        if is_output {
+            remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_to_string, remove_dir_all},
+        io::{BufRead, Read},
+    };
+
+    use super::*;
+
+    fn map_fn(mut input: BufReader<File>) -> Vec<String> {
+        let mut contents = String::new();
+        let _ = input.read_to_string(&mut contents);
+        vec![contents]
+    }
+
+    fn reduce_fn(inputs: Vec<BufReader<File>>) -> String {
+        let mut lines = inputs
+            .into_iter()
+            .flat_map(|input| input.lines().map(|l| l.unwrap_or_default()))
+            .collect::<Vec<String>>();
+        lines.sort();
+        lines.into_iter().map(|l| format!("{}\n", l)).collect()
+    }
+
+    fn stage(name: &str, input_files: Vec<PathBuf>, depends_on: Vec<&str>) -> Stage {
+        Stage {
+            name: name.to_string(),
+            working_directory: PathBuf::from("./test-data/pipeline_runs_stages").join(name),
+            input_files,
+            depends_on: depends_on.into_iter().map(|d| d.to_string()).collect(),
+            map: Arc::new(map_fn),
+            reduce: Arc::new(reduce_fn),
+            n_workers: 2,
+        }
+    }
+
+    #[test]
+    fn pipeline_runs_stages() {
+        let root = PathBuf::from("./test-data/pipeline_runs_stages");
+        let pipeline = Pipeline::new(vec![
+            stage("merge", vec![], vec!["left", "right"]),
+            stage("left", vec![root.join("input_1")], vec![]),
+            stage("right", vec![root.join("input_2")], vec![]),
+        ])
+        .unwrap();
+
+        let results = pipeline.run().unwrap();
+
+        assert_eq!(results.len(), 1);
+        let merged = &results["merge"];
+        assert_eq!(merged.len(), 1);
+        assert_eq!(read_to_string(&merged[0]).unwrap(), "a\nb\nc\nd\n");
+        for upstream in ["left", "right"] {
+            assert_eq!(read_dir(root.join(upstream)).unwrap().count(), 0);
+        }
+
+        for name in ["merge", "left", "right"] {
+            let _ = remove_dir_all(root.join(name));
+        }
+    }
+
+    #[test]
+    fn pipeline_rejects_invalid_graphs() {
+        let unknown = Pipeline::new(vec![stage("a", vec![], vec!["b"])]);
+        assert!(matches!(
+            unknown,
+            Err(PipelineError::UnknownDependency { .. })
+        ));
+
+        let cycle = Pipeline::new(vec![
+            stage("a", vec![], vec!["b"]),
+            stage("b", vec![], vec!["a"]),
+            stage("c", vec![], vec![]),
+        ]);
+        match cycle {
+            Err(PipelineError::Cycle(stages)) => assert_eq!(stages, vec!["a", "b"]),
+            _ => panic!("expected a cycle"),
+        }
+
+        let duplicate = Pipeline::new(vec![stage("a", vec![], vec![]), stage("a", vec![], vec![])]);
+        assert!(matches!(duplicate, Err(PipelineError::DuplicateStage(_))));
+    }
+
+    #[test]
+    fn pipeline_stops_at_failed_stage() {
+        let root = PathBuf::from("./test-data/pipeline_stops_at_failed_stage");
+        let in_root = |stage: Stage| Stage {
+            working_directory: root.join(&stage.name),
+            ..stage
+        };
+        let failing = Stage {
+            map: Arc::new(|_| panic!("bad record")),
+            ..in_root(stage("parse", vec![root.join("input_1")], vec![]))
+        };
+        let pipeline = Pipeline::new(vec![
+            failing,
+            in_root(stage("count", vec![], vec!["parse"])),
+        ])
+        .unwrap();
+
+        match pipeline.run() {
+            Err(PipelineError::StageFailed { stage, error }) => {
+                assert_eq!(stage, "parse");
+                assert!(error.contains("bad record"), "{}", error);
+            }
+            other => panic!("expected a failed stage, got {:?}", other),
+        }
+        assert!(!root.join("count").exists());
+
+        let _ = remove_dir_all(root.join("parse"));
+    }
+}
@@ -0,0 +1,250 @@
+// A dry run: what `Master::run` would do with a job, worked out without
+// running it. The only user code called is the map function, on the
+// inputs picked for sampling, and its output is measured, never written.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{checkpoint::WAL_NAME, output::MERGED_NAME};
+
+pub struct MapTask {
+    pub id: i32,
+    pub input: PathBuf,
+    pub bytes: u64,
+    /// Its outputs are in the map cache, so it will not run.
+    pub cached: bool,
+}
+
+/// Intermediate volume extrapolated from mapping some of the inputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Estimate {
+    pub sampled_inputs: usize,
+    pub sampled_bytes: u64,
+    pub sampled_output_bytes: u64,
+    pub intermediate_bytes: u64,
+}
+
+impl Estimate {
+    /// Scales the sampled output to `input_bytes` of input over `inputs`
+    /// files; by file count when the sampled inputs are empty.
+    pub fn extrapolate(mut self, inputs: usize, input_bytes: u64) -> Self {
+        let (total, sampled) = match self.sampled_bytes {
+            0 => (inputs as u128, self.sampled_inputs as u128),
+            sampled => (input_bytes as u128, sampled as u128),
+        };
+        self.intermediate_bytes = match sampled {
+            0 => 0,
+            sampled => (self.sampled_output_bytes as u128 * total / sampled) as u64,
+        };
+        self
+    }
+}
+
+pub struct Plan {
+    pub working_directory: PathBuf,
+    pub map_tasks: Vec<MapTask>,
+    /// None when only running the map function tells, and it was not
+    /// sampled. Zero for a map-only job.
+    pub n_reduce: Option<usize>,
+    /// Upper bounds of the key ranges, when partitioning by range.
+    pub split_points: Vec<String>,
+    pub intermediate: Option<Estimate>,
+    /// Map output is kept in memory, not in the working directory.
+    pub in_memory_shuffle: bool,
+    /// The result files are merged into one at the end.
+    pub merge_output: bool,
+}
+
+impl Plan {
+    pub fn log_file(&self) -> PathBuf {
+        self.working_directory.join(WAL_NAME)
+    }
+
+    /// The map output files the job leaves in the working directory.
+    pub fn intermediate_files(&self) -> Vec<PathBuf> {
+        match self.n_reduce {
+            Some(n_reduce) if !self.in_memory_shuffle => self
+                .map_tasks
+                .iter()
+                .flat_map(|task| {
+                    (1..=n_reduce).map(move |i| {
+                        self.working_directory
+                            .join(format!("map.{}.reduce.{}", task.id, i))
+                    })
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The result files, at most one per reduce task; a partition no map
+    /// task writes to has none.
+    pub fn result_files(&self) -> Vec<PathBuf> {
+        match self.n_reduce {
+            Some(0) => self
+                .map_tasks
+                .iter()
+                .map(|task| {
+                    self.working_directory
+                        .join(format!("map.{}.result", task.id))
+                })
+                .collect(),
+            Some(n_reduce) => (1..=n_reduce)
+                .map(|i| self.working_directory.join(format!("reduce.{}.result", i)))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// Which of `n` inputs to sample: `k` of them, spread evenly.
+pub(crate) fn sample_indices(n: usize, k: usize) -> Vec<usize> {
+    let k = k.min(n);
+    (0..k).map(|i| i * n / k).collect()
+}
+
+fn files(f: &mut fmt::Formatter, what: &str, files: &[PathBuf]) -> fmt::Result {
+    let name = |path: &Path| path.display().to_string();
+    match files {
+        [] => Ok(()),
+        [only] => writeln!(f, "  {}  ({})", name(only), what),
+        [first, .., last] => writeln!(
+            f,
+            "  {} .. {}  ({} {} files)",
+            name(first),
+            name(last),
+            files.len(),
+            what
+        ),
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cached = self.map_tasks.iter().filter(|task| task.cached).count();
+        writeln!(f, "map tasks: {} ({} cached)", self.map_tasks.len(), cached)?;
+        for task in &self.map_tasks {
+            write!(
+                f,
+                "  map {}: {} ({} bytes)",
+                task.id,
+                task.input.display(),
+                task.bytes
+            )?;
+            writeln!(f, "{}", if task.cached { " cached" } else { "" })?;
+        }
+
+        match self.n_reduce {
+            Some(0) => writeln!(f, "reduce tasks: none, map output is final")?,
+            Some(n_reduce) => writeln!(f, "reduce tasks: {}", n_reduce)?,
+            None => writeln!(f, "reduce tasks: unknown until the map function runs")?,
+        }
+        if !self.split_points.is_empty() {
+            writeln!(f, "split points: {:?}", self.split_points)?;
+        }
+
+        match &self.intermediate {
+            Some(estimate) => writeln!(
+                f,
+                "intermediate: ~{} bytes, from {} bytes of output for {} of {} inputs",
+                estimate.intermediate_bytes,
+                estimate.sampled_output_bytes,
+                estimate.sampled_inputs,
+                self.map_tasks.len()
+            )?,
+            None if self.n_reduce == Some(0) => {}
+            None => writeln!(f, "intermediate: not sampled")?,
+        }
+
+        writeln!(f, "layout:")?;
+        writeln!(f, "  {}  (log)", self.log_file().display())?;
+        if self.in_memory_shuffle {
+            writeln!(f, "  map output kept in memory")?;
+        }
+        files(f, "intermediate", &self.intermediate_files())?;
+        files(f, "result", &self.result_files())?;
+        if self.merge_output {
+            let merged = self.working_directory.join(MERGED_NAME);
+            writeln!(f, "  {}  (merged results)", merged.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{metadata, read_dir, remove_dir_all};
+
+    use super::*;
+    use crate::{master::Master, pipeline::remove_job_outputs, spec::JobSpec};
+
+    #[test]
+    fn plan_describes_job_without_running_it() {
+        let directory = PathBuf::from("./test-data/plan_describes_job");
+        let cache = directory.join("cache");
+        let spec = JobSpec {
+            n_reduce: 3,
+            combiner: true,
+            working_directory: directory.clone(),
+            map_cache: Some(cache.clone()),
+            ..JobSpec::new(
+                "wc",
+                (1..=3)
+                    .map(|i| directory.join(format!("input_{}", i)))
+                    .collect(),
+            )
+            .unwrap()
+        };
+        let master = Master::from_spec(&spec).unwrap();
+        let listing = || {
+            let mut names = read_dir(&directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let before = listing();
+
+        let plan = master.plan(3).unwrap();
+        assert_eq!(listing(), before);
+        assert_eq!(plan.map_tasks.len(), 3);
+        assert!(plan.map_tasks.iter().all(|task| !task.cached));
+        assert_eq!(plan.n_reduce, Some(3));
+        assert_eq!(plan.intermediate_files().len(), 9);
+        assert_eq!(plan.result_files()[2], directory.join("reduce.3.result"));
+        assert!(plan.to_string().contains("map tasks: 3 (0 cached)"));
+
+        // Sampling every input measures exactly what the maps write.
+        master.run(2);
+        let written = plan
+            .intermediate_files()
+            .iter()
+            .map(|path| metadata(path).unwrap().len())
+            .sum::<u64>();
+        let estimate = plan.intermediate.unwrap();
+        assert_eq!(estimate.sampled_inputs, 3);
+        assert_eq!(estimate.intermediate_bytes, written);
+        remove_job_outputs(&directory).unwrap();
+
+        let plan = master.plan(0).unwrap();
+        assert!(plan.map_tasks.iter().all(|task| task.cached));
+        assert_eq!(plan.intermediate, None);
+        remove_dir_all(cache).unwrap();
+    }
+
+    #[test]
+    fn estimate_scales_sample_to_all_inputs() {
+        let estimate = Estimate {
+            sampled_inputs: 1,
+            sampled_bytes: 100,
+            sampled_output_bytes: 150,
+            intermediate_bytes: 0,
+        };
+        assert_eq!(estimate.extrapolate(4, 1000).intermediate_bytes, 1500);
+        assert_eq!(sample_indices(10, 3), vec![0, 3, 6]);
+        assert_eq!(sample_indices(2, 5), vec![0, 1]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{self, BufRead, Lines};
+
+use crate::worker::KeyValue;
+
+// Intermediate and keyed result files hold one record per line, the key
+// and value separated by a tab. Tabs, newlines and backslashes inside keys
+// and values are escaped so any string round-trips.
+
+pub fn encode(kv: &KeyValue) -> String {
+    format!("{}\t{}", escape(&kv.key), escape(&kv.value))
+}
+
+pub fn decode(line: &str) -> Option<KeyValue> {
+    let (key, value) = line.split_once('\t')?;
+    Some(KeyValue {
+        key: unescape(key)?,
+        value: unescape(value)?,
+    })
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            unescaped.push(match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                _ => return None,
+            });
+        } else {
+            unescaped.push(c);
+        }
+    }
+    Some(unescaped)
+}
+
+/// Streams the records of an encoded file. A line that cannot be read or
+/// decoded is an error, never skipped.
+pub struct Records<R> {
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R) -> Self {
+        Records {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = io::Result<KeyValue>;
+
+    fn next(&mut self) -> Option<io::Result<KeyValue>> {
+        let line = self.lines.next()?;
+        self.line += 1;
+        Some(line.and_then(|line| {
+            decode(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: not a record: {:?}", self.line, line),
+                )
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            KeyValue {
+                key: "plain".to_string(),
+                value: "1".to_string(),
+            },
+            KeyValue {
+                key: "tab\there".to_string(),
+                value: "line\nbreak \\ slash".to_string(),
+            },
+            KeyValue {
+                key: "".to_string(),
+                value: "".to_string(),
+            },
+        ];
+
+        let encoded = records
+            .iter()
+            .map(|kv| format!("{}\n", encode(kv)))
+            .collect::<String>();
+
+        assert_eq!(encoded.lines().count(), 3);
+        assert_eq!(
+            Records::new(encoded.as_bytes())
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap(),
+            records
+        );
+
+        let corrupt = format!("{}no separator\n{}", encoded, encoded);
+        let decoded = Records::new(corrupt.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(decoded.len(), 7);
+        let error = decoded[3].as_ref().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "line 4: not a record: \"no separator\"");
+        assert_eq!(decode("no separator"), None);
+        assert_eq!(decode("bad\\x\t1"), None);
+    }
+}
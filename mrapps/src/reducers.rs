@@ -0,0 +1,558 @@
+// Ready-made reducers for `Keyed` jobs. Each is an `Aggregate`: a map
+// function emits values with `emit`, and `reducer` folds the values of a
+// key into its final value. Intermediate values are encoded partial
+// aggregates and merging them is associative and commutative, so
+// `combiner` may fold any subset of a key's values on the map side, any
+// number of times, without changing the result.
+//
+// The exception is adding floats, which rounds: `Sum<f64>` and `Average`
+// may come out differently depending on how values were grouped, by a few
+// units in the last place for every value added. Partial sums are encoded
+// so they parse back exactly, so only the additions themselves round.
+// `Histogram` takes floats but only adds integer counts, so it is exact.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    marker::PhantomData,
+    ops::Add,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{keyed::KeyedReduce, worker::KeyValue};
+
+pub trait Aggregate: Send + Sync + 'static {
+    /// What a map function emits for one record.
+    type Input;
+    /// A partial aggregate of some of a key's values.
+    type State;
+
+    fn lift(&self, input: Self::Input) -> Self::State;
+    fn merge(&self, a: Self::State, b: Self::State) -> Self::State;
+    fn encode(&self, state: &Self::State) -> String;
+    fn decode(&self, value: &str) -> Option<Self::State>;
+    /// The value a reduce task writes for the key.
+    fn finish(&self, state: Self::State) -> String;
+}
+
+/// The intermediate record a map function emits for `input` under `key`.
+pub fn emit<A: Aggregate>(aggregate: &A, key: &str, input: A::Input) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: aggregate.encode(&aggregate.lift(input)),
+    }
+}
+
+// Groups are never empty. A value that does not decode fails the task.
+fn fold<A: Aggregate>(
+    aggregate: &A,
+    key: &str,
+    values: &mut dyn Iterator<Item = KeyValue>,
+) -> A::State {
+    values
+        .map(|kv| {
+            aggregate
+                .decode(&kv.value)
+                .unwrap_or_else(|| panic!("{}: cannot decode value `{}`", key, kv.value))
+        })
+        .reduce(|a, b| aggregate.merge(a, b))
+        .unwrap()
+}
+
+pub fn reducer<A: Aggregate>(aggregate: A) -> KeyedReduce {
+    Arc::new(move |key, values| {
+        let state = fold(&aggregate, key, values);
+        vec![KeyValue {
+            key: key.to_string(),
+            value: aggregate.finish(state),
+        }]
+    })
+}
+
+pub fn combiner<A: Aggregate>(aggregate: A) -> KeyedReduce {
+    Arc::new(move |key, values| {
+        let state = fold(&aggregate, key, values);
+        vec![KeyValue {
+            key: key.to_string(),
+            value: aggregate.encode(&state),
+        }]
+    })
+}
+
+/// Counts records, as `wc::reduce` does. Map functions emit `()`, which
+/// encodes as "1".
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Count;
+
+impl Aggregate for Count {
+    type Input = ();
+    type State = u64;
+
+    fn lift(&self, _: ()) -> u64 {
+        1
+    }
+
+    fn merge(&self, a: u64, b: u64) -> u64 {
+        a + b
+    }
+
+    fn encode(&self, state: &u64) -> String {
+        state.to_string()
+    }
+
+    fn decode(&self, value: &str) -> Option<u64> {
+        value.parse().ok()
+    }
+
+    fn finish(&self, state: u64) -> String {
+        state.to_string()
+    }
+}
+
+/// Adds up numbers of type `T`. Float sums depend on grouping only up to
+/// rounding.
+#[derive(Clone, Copy, Debug)]
+pub struct Sum<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for Sum<T> {
+    fn default() -> Self {
+        Sum(PhantomData)
+    }
+}
+
+impl<T> Aggregate for Sum<T>
+where
+    T: Add<Output = T> + FromStr + Display + 'static,
+{
+    type Input = T;
+    type State = T;
+
+    fn lift(&self, input: T) -> T {
+        input
+    }
+
+    fn merge(&self, a: T, b: T) -> T {
+        a + b
+    }
+
+    fn encode(&self, state: &T) -> String {
+        state.to_string()
+    }
+
+    fn decode(&self, value: &str) -> Option<T> {
+        value.parse().ok()
+    }
+
+    fn finish(&self, state: T) -> String {
+        state.to_string()
+    }
+}
+
+/// Keeps the smallest value.
+#[derive(Clone, Copy, Debug)]
+pub struct Min<T>(PhantomData<fn() -> T>);
+
+/// Keeps the largest value.
+#[derive(Clone, Copy, Debug)]
+pub struct Max<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for Min<T> {
+    fn default() -> Self {
+        Min(PhantomData)
+    }
+}
+
+impl<T> Default for Max<T> {
+    fn default() -> Self {
+        Max(PhantomData)
+    }
+}
+
+impl<T> Aggregate for Min<T>
+where
+    T: Ord + FromStr + Display + 'static,
+{
+    type Input = T;
+    type State = T;
+
+    fn lift(&self, input: T) -> T {
+        input
+    }
+
+    fn merge(&self, a: T, b: T) -> T {
+        a.min(b)
+    }
+
+    fn encode(&self, state: &T) -> String {
+        state.to_string()
+    }
+
+    fn decode(&self, value: &str) -> Option<T> {
+        value.parse().ok()
+    }
+
+    fn finish(&self, state: T) -> String {
+        state.to_string()
+    }
+}
+
+impl<T> Aggregate for Max<T>
+where
+    T: Ord + FromStr + Display + 'static,
+{
+    type Input = T;
+    type State = T;
+
+    fn lift(&self, input: T) -> T {
+        input
+    }
+
+    fn merge(&self, a: T, b: T) -> T {
+        a.max(b)
+    }
+
+    fn encode(&self, state: &T) -> String {
+        state.to_string()
+    }
+
+    fn decode(&self, value: &str) -> Option<T> {
+        value.parse().ok()
+    }
+
+    fn finish(&self, state: T) -> String {
+        state.to_string()
+    }
+}
+
+/// The mean of the values, carried as a sum and a count until the end.
+/// Like `Sum<f64>` it depends on grouping only up to rounding.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Average;
+
+impl Aggregate for Average {
+    type Input = f64;
+    type State = (f64, u64);
+
+    fn lift(&self, input: f64) -> (f64, u64) {
+        (input, 1)
+    }
+
+    fn merge(&self, a: (f64, u64), b: (f64, u64)) -> (f64, u64) {
+        (a.0 + b.0, a.1 + b.1)
+    }
+
+    fn encode(&self, (sum, count): &(f64, u64)) -> String {
+        format!("{}/{}", sum, count)
+    }
+
+    fn decode(&self, value: &str) -> Option<(f64, u64)> {
+        let (sum, count) = value.split_once('/')?;
+        Some((sum.parse().ok()?, count.parse().ok()?))
+    }
+
+    fn finish(&self, (sum, count): (f64, u64)) -> String {
+        (sum / count as f64).to_string()
+    }
+}
+
+/// The number of different values. Every distinct value is shuffled; see
+/// `sketch::Distinct` for an estimate that shuffles a fixed size sketch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DistinctCount;
+
+impl Aggregate for DistinctCount {
+    type Input = String;
+    type State = BTreeSet<String>;
+
+    fn lift(&self, input: String) -> BTreeSet<String> {
+        BTreeSet::from([input])
+    }
+
+    fn merge(&self, mut a: BTreeSet<String>, mut b: BTreeSet<String>) -> BTreeSet<String> {
+        a.append(&mut b);
+        a
+    }
+
+    fn encode(&self, state: &BTreeSet<String>) -> String {
+        join_list(state.iter().cloned())
+    }
+
+    fn decode(&self, value: &str) -> Option<BTreeSet<String>> {
+        split_list(value).map(BTreeSet::from_iter)
+    }
+
+    fn finish(&self, state: BTreeSet<String>) -> String {
+        state.len().to_string()
+    }
+}
+
+/// The `k` highest scoring items, written as `score:item` pairs, highest
+/// first. Equal scores are ordered by item so the result is stable.
+#[derive(Clone, Copy, Debug)]
+pub struct TopK {
+    pub k: usize,
+}
+
+impl Aggregate for TopK {
+    /// An item and its score.
+    type Input = (String, i64);
+    type State = Vec<(i64, String)>;
+
+    fn lift(&self, (item, score): (String, i64)) -> Vec<(i64, String)> {
+        vec![(score, item)]
+    }
+
+    fn merge(&self, mut a: Vec<(i64, String)>, b: Vec<(i64, String)>) -> Vec<(i64, String)> {
+        a.extend(b);
+        a.sort_by(|x, y| y.0.cmp(&x.0).then_with(|| x.1.cmp(&y.1)));
+        a.truncate(self.k.max(1));
+        a
+    }
+
+    fn encode(&self, state: &Vec<(i64, String)>) -> String {
+        join_list(
+            state
+                .iter()
+                .map(|(score, item)| format!("{}:{}", score, item)),
+        )
+    }
+
+    fn decode(&self, value: &str) -> Option<Vec<(i64, String)>> {
+        split_list(value)?
+            .into_iter()
+            .map(|pair| {
+                let (score, item) = pair.split_once(':')?;
+                Some((score.parse().ok()?, item.to_string()))
+            })
+            .collect()
+    }
+
+    fn finish(&self, state: Vec<(i64, String)>) -> String {
+        self.encode(&state)
+    }
+}
+
+/// Counts values per bucket of `width`, written as `lower_bound:count`
+/// pairs in increasing order.
+#[derive(Clone, Copy, Debug)]
+pub struct Histogram {
+    pub width: f64,
+}
+
+impl Aggregate for Histogram {
+    type Input = f64;
+    /// Counts by bucket number.
+    type State = BTreeMap<i64, u64>;
+
+    fn lift(&self, input: f64) -> BTreeMap<i64, u64> {
+        BTreeMap::from([((input / self.width).floor() as i64, 1)])
+    }
+
+    fn merge(&self, mut a: BTreeMap<i64, u64>, b: BTreeMap<i64, u64>) -> BTreeMap<i64, u64> {
+        for (bucket, count) in b {
+            *a.entry(bucket).or_default() += count;
+        }
+        a
+    }
+
+    fn encode(&self, state: &BTreeMap<i64, u64>) -> String {
+        join_list(
+            state
+                .iter()
+                .map(|(bucket, count)| format!("{}:{}", bucket, count)),
+        )
+    }
+
+    fn decode(&self, value: &str) -> Option<BTreeMap<i64, u64>> {
+        split_list(value)?
+            .into_iter()
+            .map(|pair| {
+                let (bucket, count) = pair.split_once(':')?;
+                Some((bucket.parse().ok()?, count.parse().ok()?))
+            })
+            .collect()
+    }
+
+    fn finish(&self, state: BTreeMap<i64, u64>) -> String {
+        join_list(
+            state
+                .into_iter()
+                .map(|(bucket, count)| format!("{}:{}", bucket as f64 * self.width, count)),
+        )
+    }
+}
+
+// Comma separated, with commas and backslashes in items escaped.
+fn join_list<I: Iterator<Item = String>>(items: I) -> String {
+    items
+        .map(|item| item.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn split_list(list: &str) -> Option<Vec<String>> {
+    let mut items = vec![String::new()];
+    let mut chars = list.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => items.last_mut()?.push(chars.next()?),
+            ',' => items.push(String::new()),
+            c => items.last_mut()?.push(c),
+        }
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn reduce_all<A: Aggregate + Clone>(aggregate: &A, values: Vec<KeyValue>) -> String {
+        reducer(aggregate.clone())("k", &mut values.into_iter())
+            .pop()
+            .unwrap()
+            .value
+    }
+
+    // Combines the values in the chunks `cuts` splits them into, then
+    // combines some of the combined values again, in reverse order, before
+    // reducing. Returns the results of reducing without and with combining.
+    fn reduce_combined<A: Aggregate + Clone>(
+        aggregate: &A,
+        inputs: Vec<A::Input>,
+        cuts: Vec<usize>,
+    ) -> (String, String) {
+        let values = inputs
+            .into_iter()
+            .map(|input| emit(aggregate, "k", input))
+            .collect::<Vec<KeyValue>>();
+        let expected = reduce_all(aggregate, values.clone());
+
+        let mut cuts = cuts
+            .into_iter()
+            .map(|cut| cut % (values.len() + 1))
+            .chain([0, values.len()])
+            .collect::<Vec<usize>>();
+        cuts.sort();
+        cuts.dedup();
+        let combine = combiner(aggregate.clone());
+        let mut combined = cuts
+            .windows(2)
+            .flat_map(|w| combine("k", &mut values[w[0]..w[1]].iter().cloned()))
+            .collect::<Vec<KeyValue>>();
+        combined.reverse();
+        let half = combined.len() / 2;
+        if half > 0 {
+            let again = combine("k", &mut combined.drain(..half));
+            combined.extend(again);
+        }
+
+        (expected, reduce_all(aggregate, combined))
+    }
+
+    fn check_combiner_safe<A: Aggregate + Clone>(
+        aggregate: A,
+        inputs: Vec<A::Input>,
+        cuts: Vec<usize>,
+    ) -> Result<(), TestCaseError> {
+        let (expected, combined) = reduce_combined(&aggregate, inputs, cuts);
+        prop_assert_eq!(combined, expected);
+        Ok(())
+    }
+
+    // Float addition rounds at every step, so regrouping may move a sum by
+    // a few ulps of the magnitudes added for each value.
+    fn check_combiner_rounding<A: Aggregate<Input = f64> + Clone>(
+        aggregate: A,
+        inputs: Vec<f64>,
+        cuts: Vec<usize>,
+        scale: f64,
+    ) -> Result<(), TestCaseError> {
+        let magnitude = inputs.iter().map(|x| x.abs()).sum::<f64>() / scale;
+        let tolerance = 4.0 * inputs.len() as f64 * f64::EPSILON * magnitude;
+        let (expected, combined) = reduce_combined(&aggregate, inputs, cuts);
+        let (expected, combined) = (
+            expected.parse::<f64>().unwrap(),
+            combined.parse::<f64>().unwrap(),
+        );
+        prop_assert!(
+            (expected - combined).abs() <= tolerance,
+            "{} and {} differ by more than {}",
+            expected,
+            combined,
+            tolerance
+        );
+        Ok(())
+    }
+
+    fn item() -> impl Strategy<Value = String> {
+        // Commas, colons and backslashes exercise the list encoding.
+        "[a-c,:\\\\]{0,3}"
+    }
+
+    proptest! {
+        #[test]
+        fn associative_reducers_are_combiner_safe(
+            numbers in prop::collection::vec(-1000i64..1000, 1..40),
+            items in prop::collection::vec((item(), -5i64..5), 1..40),
+            cuts in prop::collection::vec(any::<usize>(), 0..6),
+            k in 1usize..5,
+        ) {
+            let floats = numbers.iter().map(|n| *n as f64).collect::<Vec<f64>>();
+            let words = items.iter().map(|(item, _)| item.clone()).collect::<Vec<String>>();
+
+            check_combiner_safe(Count, vec![(); numbers.len()], cuts.clone())?;
+            check_combiner_safe(Sum::<i64>::default(), numbers.clone(), cuts.clone())?;
+            check_combiner_safe(Min::<i64>::default(), numbers.clone(), cuts.clone())?;
+            check_combiner_safe(Max::<String>::default(), words.clone(), cuts.clone())?;
+            check_combiner_safe(Average, floats.clone(), cuts.clone())?;
+            check_combiner_safe(DistinctCount, words, cuts.clone())?;
+            check_combiner_safe(TopK { k }, items, cuts.clone())?;
+            check_combiner_safe(Histogram { width: 100.0 }, floats, cuts)?;
+        }
+
+        #[test]
+        fn float_reducers_are_combiner_safe_up_to_rounding(
+            floats in prop::collection::vec(-1e12f64..1e12, 1..40),
+            cuts in prop::collection::vec(any::<usize>(), 0..6),
+            width in 1e-3f64..1e3,
+        ) {
+            let n = floats.len() as f64;
+            check_combiner_rounding(Sum::<f64>::default(), floats.clone(), cuts.clone(), 1.0)?;
+            check_combiner_rounding(Average, floats.clone(), cuts.clone(), n)?;
+            check_combiner_safe(Histogram { width }, floats, cuts)?;
+        }
+    }
+
+    #[test]
+    fn reducers_write_final_values() {
+        let values = |aggregate: &dyn Fn(&str) -> KeyValue, inputs: &[&str]| {
+            inputs
+                .iter()
+                .map(|i| aggregate(i))
+                .collect::<Vec<KeyValue>>()
+        };
+
+        let average = values(
+            &|i| emit(&Average, "k", i.parse().unwrap()),
+            &["1", "2", "6"],
+        );
+        assert_eq!(reduce_all(&Average, average), "3");
+
+        let top = values(
+            &|i| emit(&TopK { k: 2 }, "k", (i.to_string(), i.len() as i64)),
+            &["a", "ccc", "bb", "dd"],
+        );
+        assert_eq!(reduce_all(&TopK { k: 2 }, top), "3:ccc,2:bb");
+
+        let histogram = Histogram { width: 10.0 };
+        let buckets = values(
+            &|i| emit(&histogram, "k", i.parse().unwrap()),
+            &["1", "25", "-3", "29"],
+        );
+        assert_eq!(reduce_all(&histogram, buckets), "-10:1,0:1,20:2");
+    }
+}
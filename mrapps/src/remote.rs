@@ -0,0 +1,476 @@
+// Workers in other processes, connected to the master over TCP. Each side
+// sends one JSON object per line: the master a `Job`, the worker the
+// `JobResult` once the job is done, and a connection carries one job at a
+// time.
+//
+// The master's end runs on the workspace's futures runtime: every
+// connection is a task on one executor thread and the reactor wakes it
+// when its socket is ready, so thousands of workers cost no thread each.
+// Scheduling stays in `Master::drive`, which hands jobs to the connection
+// tasks through a `Hub` and takes their results from a channel.
+//
+// Workers must see the input and working directory paths the master sends
+// them, as on a shared file system.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::{poll_fn, Future},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    panic::resume_unwind,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Mutex, Once},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Instant,
+};
+
+use chan::Sender;
+use mio::{net::TcpStream, Interest};
+use rust_futures::{
+    executor::{spawn, Executor},
+    reactor::{reactor, start},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    master::Master,
+    status::TaskId,
+    transport::Channels,
+    worker::{Job, JobResult, Worker},
+};
+
+/// Listens for remote workers and runs jobs on those that connect.
+pub struct RemoteWorkers {
+    listener: TcpListener,
+}
+
+impl RemoteWorkers {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(RemoteWorkers {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Runs `master`'s job on the workers that connect while it runs, with
+    /// at most `max_outstanding` tasks sent out but not finished. Tasks
+    /// queue until a worker is free. The workers are disconnected at the
+    /// end. Map output must go through the working directory.
+    pub fn run(&self, master: &Master, max_outstanding: usize) -> io::Result<Vec<PathBuf>> {
+        if master.shuffles_in_memory() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "remote workers cannot share an in-memory shuffle",
+            ));
+        }
+        let listener = self.listener.try_clone()?;
+        listener.set_nonblocking(true)?;
+        let listener = mio::net::TcpListener::from_std(listener);
+
+        let hub = Arc::new(Hub::default());
+        let (results_send, results_queue) = chan::r#async();
+        let io = {
+            let hub = hub.clone();
+            thread::spawn(move || {
+                start_reactor();
+                Executor::new().block_on(accept(listener, hub, results_send));
+            })
+        };
+
+        let mut transport = Channels {
+            send: &|job| hub.push(job),
+            results_queue: &results_queue,
+            started: Instant::now(),
+        };
+        let result_files = master.drive(&mut transport, max_outstanding.max(1));
+
+        hub.close();
+        if let Err(panic) = io.join() {
+            resume_unwind(panic);
+        }
+        Ok(result_files)
+    }
+}
+
+/// Connects `slots` times to the master at `addr` and runs the jobs it
+/// sends with `master`'s map and reduce functions, one job per slot at a
+/// time. Returns once the master disconnects.
+pub fn work<A: ToSocketAddrs>(master: &Master, addr: A, slots: usize) -> io::Result<()> {
+    let worker = Arc::new(master.worker());
+    let streams = (0..slots.max(1))
+        .map(|_| {
+            let stream = std::net::TcpStream::connect(&addr)?;
+            stream.set_nonblocking(true)?;
+            Ok(TcpStream::from_std(stream))
+        })
+        .collect::<io::Result<Vec<TcpStream>>>()?;
+
+    start_reactor();
+    let outcome = Arc::new(Mutex::new(Ok(())));
+    let first_error = outcome.clone();
+    Executor::new().block_on(async move {
+        for (slot, stream) in streams.into_iter().enumerate() {
+            let worker = worker.clone();
+            let outcome = first_error.clone();
+            spawn(async move {
+                if let Err(e) = serve_master(slot, stream, worker).await {
+                    let mut outcome = outcome.lock().unwrap();
+                    if outcome.is_ok() {
+                        *outcome = Err(e);
+                    }
+                }
+            });
+        }
+    });
+    let mut outcome = outcome.lock().unwrap();
+    std::mem::replace(&mut *outcome, Ok(()))
+}
+
+// The reactor is global; a second one would never be polled.
+fn start_reactor() {
+    static STARTED: Once = Once::new();
+    STARTED.call_once(start);
+}
+
+/// Jobs waiting for a worker, shared by the master's thread and the
+/// connection tasks.
+#[derive(Default)]
+struct Hub {
+    state: Mutex<HubState>,
+}
+
+#[derive(Default)]
+struct HubState {
+    jobs: VecDeque<Job>,
+    // Connections waiting for a job, and those waiting for a result, by
+    // connection id.
+    idle: HashMap<usize, Waker>,
+    busy: HashMap<usize, Waker>,
+    listener: Option<Waker>,
+    closed: bool,
+}
+
+impl Hub {
+    fn push(&self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        wake_one(&mut state.idle);
+    }
+
+    /// Puts back a job a worker never received.
+    fn requeue(&self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_front(job);
+        wake_one(&mut state.idle);
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.closed = true;
+        state
+            .idle
+            .drain()
+            .chain(state.busy.drain())
+            .map(|(_, waker)| waker)
+            .chain(state.listener.take())
+            .for_each(Waker::wake);
+    }
+
+    /// The next job for connection `id`, or None once the hub is closed.
+    async fn next_job(&self, id: usize) -> Option<Job> {
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Poll::Ready(None);
+            }
+            match state.jobs.pop_front() {
+                Some(job) => {
+                    state.idle.remove(&id);
+                    Poll::Ready(Some(job))
+                }
+                None => {
+                    state.idle.insert(id, cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Runs `future` for connection `id` unless the hub closes first.
+    async fn unless_closed<F: Future>(&self, id: usize, future: F) -> Option<F::Output> {
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                self.state.lock().unwrap().busy.remove(&id);
+                return Poll::Ready(Some(output));
+            }
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Poll::Ready(None);
+            }
+            state.busy.insert(id, cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+fn wake_one(wakers: &mut HashMap<usize, Waker>) {
+    if let Some(&id) = wakers.keys().next() {
+        wakers.remove(&id).unwrap().wake();
+    }
+}
+
+async fn accept(mut listener: mio::net::TcpListener, hub: Arc<Hub>, results: Sender<JobResult>) {
+    let id = reactor().next_id();
+    reactor()
+        .register(&mut listener, Interest::READABLE, id)
+        .expect("register listener");
+    loop {
+        let accepted = poll_fn(|cx| {
+            reactor().set_waker(cx, id);
+            let mut state = hub.state.lock().unwrap();
+            if state.closed {
+                return Poll::Ready(None);
+            }
+            state.listener = Some(cx.waker().clone());
+            drop(state);
+            match listener.accept() {
+                Ok((stream, _)) => Poll::Ready(Some(Ok(stream))),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Poll::Pending,
+                Err(e) => Poll::Ready(Some(Err(e))),
+            }
+        })
+        .await;
+        match accepted {
+            Some(Ok(stream)) => {
+                if let Ok(connection) = Connection::new(stream) {
+                    spawn(serve_worker(connection, hub.clone(), results.clone()));
+                }
+            }
+            // The worker gave up before it was accepted.
+            Some(Err(_)) => {}
+            None => break,
+        }
+    }
+    let _ = reactor().deregister(&mut listener, id);
+}
+
+/// Sends a worker jobs until the hub closes or the worker goes away.
+async fn serve_worker(mut connection: Connection, hub: Arc<Hub>, results: Sender<JobResult>) {
+    let id = connection.id;
+    while let Some(job) = hub.next_job(id).await {
+        if connection.send(&job).await.is_err() {
+            hub.requeue(job);
+            return;
+        }
+        let task = job.task_id();
+        match hub.unless_closed(id, connection.receive()).await {
+            Some(Ok(Some(result))) => results.send(result),
+            Some(_) => {
+                results.send(lost(task));
+                return;
+            }
+            None => return,
+        }
+    }
+}
+
+fn lost(task: TaskId) -> JobResult {
+    let error = "worker disconnected".to_string();
+    match task {
+        TaskId::Map(id) => JobResult::MapFailed(id, error),
+        TaskId::Reduce(id) => JobResult::ReduceFailed(id, error),
+    }
+}
+
+/// Runs the jobs the master sends on a connection until it disconnects.
+/// Jobs run on a thread of their own so the other slots keep going.
+async fn serve_master(slot: usize, stream: TcpStream, worker: Arc<Worker>) -> io::Result<()> {
+    let mut connection = Connection::new(stream)?;
+    while let Some(job) = connection.receive::<Job>().await? {
+        let worker = worker.clone();
+        let result = blocking(move || worker.process(slot, job)).await;
+        connection.send(&result).await?;
+    }
+    Ok(())
+}
+
+/// Runs `f` on a new thread and waits for it without blocking the executor.
+async fn blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new((None, None::<Waker>)));
+    let done = shared.clone();
+    let handle = thread::spawn(move || {
+        let output = f();
+        let mut done = done.lock().unwrap();
+        done.0 = Some(output);
+        if let Some(waker) = done.1.take() {
+            waker.wake();
+        }
+    });
+    let output = poll_fn(|cx| {
+        let mut shared = shared.lock().unwrap();
+        match shared.0.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                shared.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    })
+    .await;
+    let _ = handle.join();
+    output
+}
+
+/// A socket registered with the reactor, read and written a line at a
+/// time.
+struct Connection {
+    stream: TcpStream,
+    id: usize,
+    received: Vec<u8>,
+}
+
+impl Connection {
+    fn new(mut stream: TcpStream) -> io::Result<Self> {
+        let id = reactor().next_id();
+        reactor().register(&mut stream, Interest::READABLE | Interest::WRITABLE, id)?;
+        Ok(Connection {
+            stream,
+            id,
+            received: vec![],
+        })
+    }
+
+    async fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        let mut written = 0;
+        poll_fn(|cx| {
+            reactor().set_waker(cx, self.id);
+            while written < line.len() {
+                match self.stream.write(&line[written..]) {
+                    Ok(0) => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                    Ok(n) => written += n,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Poll::Pending,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// The next message, or None at the end of the stream.
+    async fn receive<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        let line = poll_fn(|cx| self.poll_line(cx)).await?;
+        line.map(|line| serde_json::from_slice(&line).map_err(io::Error::from))
+            .transpose()
+    }
+
+    fn poll_line(&mut self, cx: &mut Context) -> Poll<io::Result<Option<Vec<u8>>>> {
+        // Set before reading, so readiness that arrives meanwhile wakes us.
+        reactor().set_waker(cx, self.id);
+        let mut buffer = [0; 4096];
+        loop {
+            if let Some(end) = self.received.iter().position(|&b| b == b'\n') {
+                let mut line = self.received.drain(..=end).collect::<Vec<u8>>();
+                line.pop();
+                return Poll::Ready(Ok(Some(line)));
+            }
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Poll::Ready(Ok(None)),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Poll::Pending,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = reactor().deregister(&mut self.stream, self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::read_to_string, sync::Arc};
+
+    use super::*;
+    use crate::{
+        keyed::{read_results, Keyed},
+        pipeline::remove_job_outputs,
+        worker::KeyValue,
+    };
+
+    fn word_count(directory: &std::path::Path) -> Master {
+        let keyed = Keyed::new(2);
+        Master::new(
+            directory.to_path_buf(),
+            (1..=3)
+                .map(|i| directory.join(format!("input_{}", i)))
+                .collect(),
+            keyed.map(Arc::new(|contents: String| {
+                contents
+                    .split_whitespace()
+                    .map(|word| KeyValue {
+                        key: word.to_string(),
+                        value: "1".to_string(),
+                    })
+                    .collect()
+            })),
+            keyed.reduce(Arc::new(|key, values| {
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: values.count().to_string(),
+                }]
+            })),
+        )
+    }
+
+    #[test]
+    fn remote_workers_run_job_over_sockets() {
+        let directory = PathBuf::from("./test-data/remote_workers");
+        let remote = RemoteWorkers::bind("127.0.0.1:0").unwrap();
+        let addr = remote.local_addr().unwrap();
+
+        let workers = (0..2)
+            .map(|_| {
+                let directory = directory.clone();
+                thread::spawn(move || work(&word_count(&directory), addr, 2))
+            })
+            .collect::<Vec<_>>();
+        let result_files = remote.run(&word_count(&directory), 4).unwrap();
+        for worker in workers {
+            worker.join().unwrap().unwrap();
+        }
+
+        let mut counts = result_files
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .map(|kv| (kv.key, kv.value))
+            .collect::<Vec<(String, String)>>();
+        counts.sort();
+        remove_job_outputs(&directory).unwrap();
+        assert_eq!(
+            counts,
+            [("apple", "3"), ("banana", "2"), ("cherry", "1")]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+    }
+}
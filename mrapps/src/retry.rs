@@ -0,0 +1,116 @@
+// Retries of worker I/O that fails for reasons likely to pass: running out
+// of file descriptors, disk space or memory for a moment, or an
+// interrupted call. Any other error is permanent and fails the task at
+// once, as does a transient one that outlasts every retry.
+
+use std::{io, thread, time::Duration};
+
+/// Whether `error` may go away if the operation is tried again.
+pub fn is_transient(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(code) => [
+            libc::EMFILE,
+            libc::ENFILE,
+            libc::ENOSPC,
+            libc::EDQUOT,
+            libc::ENOMEM,
+            libc::EAGAIN,
+            libc::EBUSY,
+            libc::EINTR,
+        ]
+        .contains(&code),
+        None => matches!(
+            error.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+    }
+}
+
+/// How a worker retries transient I/O errors. The delay starts at
+/// `initial_delay` and doubles after every retry, up to `max_delay`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The delay before retry `retry`, counting from zero.
+    pub fn delay(&self, retry: usize) -> Duration {
+        let factor = 1u32.checked_shl(retry as u32).unwrap_or(u32::MAX);
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+
+    /// Runs `operation` until it succeeds, fails with a permanent error or
+    /// has been retried `max_retries` times, and returns its last result.
+    pub fn run<T, F>(&self, mut operation: F) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Err(e) if retry < self.max_retries && is_transient(&e) => {
+                    thread::sleep(self.delay(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_errors_are_retried_with_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(3),
+        };
+        assert_eq!(
+            (0..5).map(|retry| policy.delay(retry)).collect::<Vec<_>>(),
+            [1, 2, 3, 3, 3].map(Duration::from_millis)
+        );
+
+        let failing = |code: i32, failures: usize| {
+            let mut attempts = 0;
+            let result = policy.run(|| {
+                attempts += 1;
+                match attempts > failures {
+                    true => Ok(attempts),
+                    false => Err(io::Error::from_raw_os_error(code)),
+                }
+            });
+            (result.map_err(|e| e.raw_os_error()), attempts)
+        };
+        assert_eq!(failing(libc::EMFILE, 2), (Ok(3), 3));
+        assert_eq!(failing(libc::ENOSPC, 3), (Ok(4), 4));
+        assert_eq!(failing(libc::ENOSPC, 4), (Err(Some(libc::ENOSPC)), 4));
+        assert_eq!(failing(libc::ENOENT, 2), (Err(Some(libc::ENOENT)), 1));
+    }
+}
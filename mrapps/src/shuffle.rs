@@ -0,0 +1,176 @@
+// Where map output waits for the reduce phase. The disk shuffle writes
+// `map.<m>.reduce.<r>` files to the working directory; the memory shuffle
+// keeps each of those partitions in memory instead, and hands every reader
+// an anonymous in-memory file of its own, so reduce functions still read
+// them as `BufReader<File>`. Like the disk shuffle, a running reduce task
+// holds one file descriptor per map task and no others are kept open.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Seek, Write},
+    os::fd::FromRawFd,
+    sync::{Arc, Mutex},
+};
+
+use crate::status::TaskId;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shuffle {
+    #[default]
+    Disk,
+    /// Intermediate data never touches the working directory and is gone
+    /// once the run ends, so a resumed job runs its map tasks again. Only
+    /// used when tasks run on threads; forked tasks shuffle through disk.
+    Memory,
+}
+
+// The output of every map task for one reduce task, by map task.
+type Buffers = BTreeMap<i32, Arc<str>>;
+
+/// Map output partitions held in memory, by reduce task and then map task.
+#[derive(Clone, Default)]
+pub struct MemoryShuffle {
+    partitions: Arc<Mutex<BTreeMap<i32, Buffers>>>,
+    // How many more writes and reads fail, and with which errno.
+    #[cfg(test)]
+    faults: Arc<Mutex<(usize, i32)>>,
+}
+
+impl MemoryShuffle {
+    /// Stores the partitions of map task `map_id`, replacing those of an
+    /// earlier attempt. Partition i is read by reduce task i + 1.
+    pub fn write(&self, map_id: i32, results: &[String]) -> io::Result<()> {
+        #[cfg(test)]
+        self.fault()?;
+        let mut partitions = self.partitions.lock().unwrap();
+        for (i, result) in results.iter().enumerate() {
+            partitions
+                .entry(i as i32 + 1)
+                .or_default()
+                .insert(map_id, Arc::from(result.as_str()));
+        }
+        Ok(())
+    }
+
+    /// The reduce tasks with input.
+    pub fn partitions(&self) -> Vec<i32> {
+        self.partitions.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Readers over every map task's output for reduce task `reduce_id`.
+    /// Each call gets readers of its own, so attempts of a reduce task may
+    /// overlap.
+    pub fn readers(&self, reduce_id: i32) -> io::Result<Vec<BufReader<File>>> {
+        #[cfg(test)]
+        self.fault()?;
+        let buffers = self
+            .partitions
+            .lock()
+            .unwrap()
+            .get(&reduce_id)
+            .map(|buffers| buffers.values().cloned().collect::<Vec<Arc<str>>>())
+            .unwrap_or_default();
+        buffers
+            .iter()
+            .map(|buffer| {
+                let mut reader = memfd()?;
+                reader.write_all(buffer.as_bytes())?;
+                reader.rewind()?;
+                Ok(BufReader::new(reader))
+            })
+            .collect()
+    }
+
+    /// The bytes map task `id` wrote, or reduce task `id` reads.
+    pub fn bytes(&self, id: TaskId) -> u64 {
+        let partitions = self.partitions.lock().unwrap();
+        let buffers = match id {
+            TaskId::Map(map_id) => partitions
+                .values()
+                .filter_map(|buffers| buffers.get(&map_id))
+                .collect::<Vec<&Arc<str>>>(),
+            TaskId::Reduce(reduce_id) => partitions
+                .get(&reduce_id)
+                .map(|buffers| buffers.values().collect())
+                .unwrap_or_default(),
+        };
+        buffers.into_iter().map(|buffer| buffer.len() as u64).sum()
+    }
+
+    pub fn clear(&self) {
+        self.partitions.lock().unwrap().clear();
+    }
+
+    /// Makes the next `count` writes and reads fail with `errno`.
+    #[cfg(test)]
+    pub(crate) fn fail_next(&self, count: usize, errno: i32) {
+        *self.faults.lock().unwrap() = (count, errno);
+    }
+
+    #[cfg(test)]
+    fn fault(&self) -> io::Result<()> {
+        let mut faults = self.faults.lock().unwrap();
+        match *faults {
+            (0, _) => Ok(()),
+            (count, errno) => {
+                *faults = (count - 1, errno);
+                Err(io::Error::from_raw_os_error(errno))
+            }
+        }
+    }
+}
+
+fn memfd() -> io::Result<File> {
+    let fd = unsafe { libc::memfd_create(c"mrapps-shuffle".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn contents(readers: Vec<BufReader<File>>) -> Vec<String> {
+        readers
+            .into_iter()
+            .map(|mut reader| {
+                let mut contents = String::new();
+                reader.read_to_string(&mut contents).unwrap();
+                contents
+            })
+            .collect()
+    }
+
+    #[test]
+    fn memory_shuffle_hands_partitions_to_reducers() {
+        let shuffle = MemoryShuffle::default();
+        shuffle
+            .write(2, &["b1".to_string(), "b2".to_string()])
+            .unwrap();
+        shuffle.write(1, &["stale".to_string()]).unwrap();
+        shuffle.write(1, &["a1".to_string()]).unwrap();
+
+        assert_eq!(shuffle.partitions(), vec![1, 2]);
+        assert_eq!(contents(shuffle.readers(1).unwrap()), vec!["a1", "b1"]);
+        // Partitions can be read again, e.g. by a retried reduce task.
+        assert_eq!(contents(shuffle.readers(1).unwrap()), vec!["a1", "b1"]);
+        assert_eq!(contents(shuffle.readers(2).unwrap()), vec!["b2"]);
+        assert!(shuffle.readers(3).unwrap().is_empty());
+
+        // Overlapping attempts read independently.
+        let mut first = shuffle.readers(2).unwrap();
+        let mut second = shuffle.readers(2).unwrap();
+        let mut byte = [0; 1];
+        first[0].read_exact(&mut byte).unwrap();
+        assert_eq!(contents(second.split_off(0)), vec!["b2"]);
+        assert_eq!(contents(first), vec!["2"]);
+
+        shuffle.clear();
+        assert!(shuffle.partitions().is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+// A deterministic stand-in for the worker pool. Tasks run one at a time on
+// the calling thread, but a seeded random number generator decides how
+// long each takes on which simulated worker and whether its worker
+// crashes or its result is delayed, lost or delivered twice. Time is
+// virtual, so task timeouts cost nothing to wait for.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    master::Master,
+    transport::{Received, Transport},
+    worker::{Job, JobResult, Worker},
+};
+
+/// The chance of each fault, between 0 and 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults {
+    /// A result reaches the master long after the task finished.
+    pub delay: f64,
+    /// A result never reaches the master.
+    pub drop: f64,
+    /// A result reaches the master twice.
+    pub duplicate: f64,
+    /// A worker crashes part way through a task and restarts later.
+    pub crash: f64,
+}
+
+pub struct Simulation {
+    pub seed: u64,
+    pub n_workers: usize,
+    pub faults: Faults,
+}
+
+impl Simulation {
+    /// Runs `master`'s job on simulated workers. Set a task timeout on the
+    /// master when results can be lost, or the job never finishes.
+    pub fn run(&self, master: &Master) -> Vec<PathBuf> {
+        let mut simulated = Simulated {
+            rng: Rng(self.seed.max(1)),
+            faults: self.faults,
+            worker: master.worker(),
+            now: Duration::ZERO,
+            queue: VecDeque::new(),
+            idle: (0..self.n_workers).collect(),
+            events: BTreeMap::new(),
+            next_event: 0,
+        };
+        master.drive(&mut simulated, 2 * self.n_workers.max(1))
+    }
+}
+
+// xorshift64
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        (self.next() % 1_000_000) as f64 / 1_000_000.0 < p
+    }
+
+    fn millis(&mut self, min: u64, max: u64) -> Duration {
+        Duration::from_millis(min + self.next() % (max - min + 1))
+    }
+}
+
+enum Event {
+    Finish(usize, Job),
+    Crash(usize),
+    Restart(usize),
+    Deliver(JobResult),
+}
+
+struct Simulated {
+    rng: Rng,
+    faults: Faults,
+    worker: Worker,
+    now: Duration,
+    queue: VecDeque<Job>,
+    idle: BTreeSet<usize>,
+    // Ordered by time, then by when they were scheduled.
+    events: BTreeMap<(Duration, u64), Event>,
+    next_event: u64,
+}
+
+impl Simulated {
+    fn schedule(&mut self, after: Duration, event: Event) {
+        self.next_event += 1;
+        self.events
+            .insert((self.now + after, self.next_event), event);
+    }
+
+    fn assign(&mut self) {
+        while !self.queue.is_empty() {
+            let Some(worker) = self.idle.pop_first() else {
+                return;
+            };
+            let job = self.queue.pop_front().unwrap();
+            let running = self.rng.millis(1, 10);
+            if self.rng.chance(self.faults.crash) {
+                self.schedule(running, Event::Crash(worker));
+            } else {
+                self.schedule(running, Event::Finish(worker, job));
+            }
+        }
+    }
+
+    fn deliver(&mut self, result: JobResult) {
+        let mut latency = self.rng.millis(0, 2);
+        if self.rng.chance(self.faults.delay) {
+            latency += self.rng.millis(50, 500);
+        }
+        self.schedule(latency, Event::Deliver(result));
+    }
+}
+
+impl Transport for Simulated {
+    fn send(&mut self, job: Job) {
+        self.queue.push_back(job);
+    }
+
+    fn recv(&mut self, deadline: Option<Duration>) -> Received {
+        loop {
+            self.assign();
+            let Some((&(time, _), _)) = self.events.first_key_value() else {
+                return match deadline {
+                    Some(deadline) => {
+                        self.now = self.now.max(deadline);
+                        Received::TimedOut
+                    }
+                    None => Received::Closed,
+                };
+            };
+            if let Some(deadline) = deadline.filter(|deadline| time > *deadline) {
+                self.now = self.now.max(deadline);
+                return Received::TimedOut;
+            }
+
+            let (_, event) = self.events.pop_first().unwrap();
+            self.now = time;
+            match event {
+                Event::Finish(worker, job) => {
+                    let result = self.worker.process(worker, job);
+                    self.idle.insert(worker);
+                    if self.rng.chance(self.faults.duplicate) {
+                        self.deliver(result.clone());
+                    }
+                    if !self.rng.chance(self.faults.drop) {
+                        self.deliver(result);
+                    }
+                }
+                Event::Crash(worker) => {
+                    let restart = self.rng.millis(20, 100);
+                    self.schedule(restart, Event::Restart(worker));
+                }
+                Event::Restart(worker) => {
+                    self.idle.insert(worker);
+                }
+                Event::Deliver(result) => return Received::Result(result),
+            }
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs::read_to_string, path::Path, sync::Arc};
+
+    use super::*;
+    use crate::{
+        checkpoint::WAL_NAME,
+        keyed::{read_results, Keyed},
+        pipeline::remove_job_outputs,
+        shuffle::Shuffle,
+        status::TaskState,
+        worker::KeyValue,
+    };
+
+    fn input_files(directory: &Path) -> Vec<PathBuf> {
+        (1..=3)
+            .map(|i| directory.join(format!("input_{}", i)))
+            .collect()
+    }
+
+    fn word_count(directory: &Path) -> Master {
+        let keyed = Keyed::new(3);
+        let mut master = Master::new(
+            directory.to_path_buf(),
+            input_files(directory),
+            keyed.map(Arc::new(|contents: String| {
+                contents
+                    .split_whitespace()
+                    .map(|word| KeyValue {
+                        key: word.to_string(),
+                        value: "1".to_string(),
+                    })
+                    .collect()
+            })),
+            keyed.reduce(Arc::new(|key, values| {
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: values.count().to_string(),
+                }]
+            })),
+        );
+        master.set_shuffle(Shuffle::Memory);
+        master.set_task_timeout(Duration::from_millis(100));
+        master.set_max_attempts(usize::MAX);
+        master
+    }
+
+    fn sequential_word_count(directory: &Path) -> BTreeMap<String, String> {
+        let mut counts = BTreeMap::<String, usize>::new();
+        for path in input_files(directory) {
+            for word in read_to_string(path).unwrap().split_whitespace() {
+                *counts.entry(word.to_string()).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(word, count)| (word, count.to_string()))
+            .collect()
+    }
+
+    const FAULTS: Faults = Faults {
+        delay: 0.2,
+        drop: 0.1,
+        duplicate: 0.1,
+        crash: 0.1,
+    };
+
+    #[test]
+    fn faulty_runs_match_sequential_output() {
+        let directory = PathBuf::from("./test-data/simulation_faulty_runs");
+        let expected = sequential_word_count(&directory);
+
+        for seed in 1..=1000 {
+            let master = word_count(&directory);
+            let simulation = Simulation {
+                seed,
+                n_workers: 1 + seed as usize % 4,
+                faults: FAULTS,
+            };
+
+            let counts = simulation
+                .run(&master)
+                .iter()
+                .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+                .map(|kv| (kv.key, kv.value))
+                .collect::<BTreeMap<String, String>>();
+
+            assert_eq!(counts, expected, "seed {}", seed);
+            let snapshot = master.status().snapshot();
+            assert!(
+                snapshot.tasks.iter().all(|t| t.state == TaskState::Done),
+                "seed {}",
+                seed
+            );
+            remove_job_outputs(&directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn same_seed_replays_same_schedule() {
+        let directory = PathBuf::from("./test-data/simulation_replays");
+        let wal = |seed| {
+            let simulation = Simulation {
+                seed,
+                n_workers: 3,
+                faults: FAULTS,
+            };
+            simulation.run(&word_count(&directory));
+            let log = read_to_string(directory.join(WAL_NAME)).unwrap();
+            remove_job_outputs(&directory).unwrap();
+            log
+        };
+
+        assert_eq!(wal(7), wal(7));
+        assert_ne!(wal(7), wal(8));
+    }
+}
@@ -0,0 +1,439 @@
+// Mergeable sketches for aggregations too large to shuffle exactly. A map
+// task builds one sketch per key over its whole split and emits it once;
+// reduce tasks, and combiners, merge the sketches of a key.
+//
+//     let aggregate = Distinct { precision: 12 };
+//     let mut sketches = HashMap::new();
+//     for (key, item) in records {
+//         sketches.entry(key).or_insert_with(|| aggregate.sketch()).insert(item);
+//     }
+//     sketches.into_iter().map(|(key, s)| emit(&aggregate, &key, s)).collect()
+
+use std::{fmt::Write, hash::Hasher};
+
+use crate::{cache::StableHasher, reducers::Aggregate};
+
+fn hash(item: &str) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write(item.as_bytes());
+    hasher.finish()
+}
+
+/// Estimates the number of distinct items with `2^precision` one byte
+/// registers, to a standard error of about `1.04 / sqrt(2^precision)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// `precision` is clamped to 4..=16.
+    pub fn new(precision: u8) -> Self {
+        let precision = precision.clamp(4, 16);
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        let h = hash(item);
+        let index = (h >> (64 - self.precision)) as usize;
+        // Position of the first set bit after the index bits.
+        let rank = ((h << self.precision) | (1 << (self.precision - 1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-(*r as i32)))
+            .sum::<f64>();
+        let raw = alpha * m * m / sum;
+
+        // Linear counting is more accurate while many registers are empty.
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let registers = self
+            .registers
+            .iter()
+            .map(|r| *r as u64)
+            .collect::<Vec<u64>>();
+        format!("hll:{}:{}", self.precision, encode_counters(&registers))
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let rest = value.strip_prefix("hll:")?;
+        let (precision, counters) = rest.split_once(':')?;
+        let precision = precision
+            .parse::<u8>()
+            .ok()
+            .filter(|p| (4..=16).contains(p))?;
+        let registers = decode_counters(counters, 1 << precision)?
+            .into_iter()
+            .map(|r| u8::try_from(r).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(HyperLogLog {
+            precision,
+            registers,
+        })
+    }
+}
+
+/// Estimates how often each item occurs. An estimate is never below the
+/// true count and, with probability `1 - e^-depth`, at most
+/// `e / width` of the total count above it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        let (width, depth) = (width.max(1), depth.max(1));
+        CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+        }
+    }
+
+    // One counter per row, from two halves of one hash.
+    fn cells(&self, item: &str) -> impl Iterator<Item = usize> {
+        let h = hash(item);
+        let (h1, h2) = (h & 0xffff_ffff, h >> 32);
+        let width = self.width as u64;
+        (0..self.depth as u64)
+            .map(move |row| (row * width + h1.wrapping_add(row.wrapping_mul(h2)) % width) as usize)
+    }
+
+    pub fn insert(&mut self, item: &str, count: u64) {
+        for cell in self.cells(item).collect::<Vec<usize>>() {
+            self.counters[cell] += count;
+        }
+    }
+
+    pub fn estimate(&self, item: &str) -> u64 {
+        self.cells(item)
+            .map(|cell| self.counters[cell])
+            .min()
+            .unwrap()
+    }
+
+    pub fn merge(&mut self, other: &CountMinSketch) {
+        for (mine, theirs) in self.counters.iter_mut().zip(&other.counters) {
+            *mine += theirs;
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "cms:{}:{}:{}",
+            self.width,
+            self.depth,
+            encode_counters(&self.counters)
+        )
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let mut fields = value.strip_prefix("cms:")?.splitn(3, ':');
+        let width = fields.next()?.parse::<usize>().ok().filter(|w| *w > 0)?;
+        let depth = fields.next()?.parse::<usize>().ok().filter(|d| *d > 0)?;
+        let counters = decode_counters(fields.next()?, width.checked_mul(depth)?)?;
+        Some(CountMinSketch {
+            width,
+            depth,
+            counters,
+        })
+    }
+}
+
+// Mostly empty counters are written sparsely as `s<index>=<count>,...`,
+// others densely as `d<count>,...`.
+fn encode_counters(counters: &[u64]) -> String {
+    let nonzero = counters.iter().filter(|c| **c != 0).count();
+    let mut encoded = String::new();
+    if nonzero * 4 < counters.len() {
+        encoded.push('s');
+        for (i, c) in counters.iter().enumerate().filter(|(_, c)| **c != 0) {
+            let _ = write!(encoded, "{}={},", i, c);
+        }
+    } else {
+        encoded.push('d');
+        for c in counters {
+            let _ = write!(encoded, "{},", c);
+        }
+    }
+    if encoded.ends_with(',') {
+        encoded.pop();
+    }
+    encoded
+}
+
+fn decode_counters(encoded: &str, len: usize) -> Option<Vec<u64>> {
+    let (kind, list) = encoded.split_at_checked(1)?;
+    let items = list.split(',').filter(|item| !item.is_empty());
+    match kind {
+        "d" => items
+            .map(|c| c.parse().ok())
+            .collect::<Option<Vec<u64>>>()
+            .filter(|counters| counters.len() == len),
+        "s" => {
+            let mut counters = vec![0; len];
+            for item in items {
+                let (i, c) = item.split_once('=')?;
+                *counters.get_mut(i.parse::<usize>().ok()?)? = c.parse().ok()?;
+            }
+            Some(counters)
+        }
+        _ => None,
+    }
+}
+
+/// Approximate distinct counts: map tasks emit `HyperLogLog` sketches and
+/// the reducer writes the estimate.
+#[derive(Clone, Copy, Debug)]
+pub struct Distinct {
+    pub precision: u8,
+}
+
+impl Distinct {
+    pub fn sketch(&self) -> HyperLogLog {
+        HyperLogLog::new(self.precision)
+    }
+}
+
+impl Aggregate for Distinct {
+    type Input = HyperLogLog;
+    type State = HyperLogLog;
+
+    fn lift(&self, input: HyperLogLog) -> HyperLogLog {
+        input
+    }
+
+    fn merge(&self, mut a: HyperLogLog, b: HyperLogLog) -> HyperLogLog {
+        a.merge(&b);
+        a
+    }
+
+    fn encode(&self, state: &HyperLogLog) -> String {
+        state.encode()
+    }
+
+    fn decode(&self, value: &str) -> Option<HyperLogLog> {
+        HyperLogLog::decode(value).filter(|s| s.precision == self.sketch().precision)
+    }
+
+    fn finish(&self, state: HyperLogLog) -> String {
+        state.estimate().to_string()
+    }
+}
+
+/// Approximate item frequencies: map tasks emit `CountMinSketch`es and the
+/// reducer writes the merged sketch, to be read back with
+/// `CountMinSketch::decode` and queried.
+#[derive(Clone, Copy, Debug)]
+pub struct Frequencies {
+    pub width: usize,
+    pub depth: usize,
+}
+
+impl Frequencies {
+    pub fn sketch(&self) -> CountMinSketch {
+        CountMinSketch::new(self.width, self.depth)
+    }
+}
+
+impl Aggregate for Frequencies {
+    type Input = CountMinSketch;
+    type State = CountMinSketch;
+
+    fn lift(&self, input: CountMinSketch) -> CountMinSketch {
+        input
+    }
+
+    fn merge(&self, mut a: CountMinSketch, b: CountMinSketch) -> CountMinSketch {
+        a.merge(&b);
+        a
+    }
+
+    fn encode(&self, state: &CountMinSketch) -> String {
+        state.encode()
+    }
+
+    fn decode(&self, value: &str) -> Option<CountMinSketch> {
+        let sketch = self.sketch();
+        CountMinSketch::decode(value).filter(|s| (s.width, s.depth) == (sketch.width, sketch.depth))
+    }
+
+    fn finish(&self, state: CountMinSketch) -> String {
+        state.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs::{read_to_string, remove_file, write},
+        path::PathBuf,
+        sync::Arc,
+    };
+
+    use super::*;
+    use crate::{
+        keyed::{read_results, Keyed},
+        master::Master,
+        pipeline::remove_job_outputs,
+        reducers::{combiner, emit, reducer},
+        worker::KeyValue,
+    };
+
+    // Skewed item ids: low ids are drawn far more often than high ones.
+    fn generated_items(n: usize, seed: u64) -> Vec<String> {
+        let mut state = seed;
+        (0..n)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let range = 1 << (state % 17);
+                format!("item-{}", (state >> 20) % range)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hyperloglog_estimates_distinct_counts() {
+        for (n, seed) in [(1_000, 1), (50_000, 2), (400_000, 3)] {
+            let items = generated_items(n, seed);
+            let exact = items.iter().collect::<HashSet<_>>().len() as f64;
+
+            // Eight map tasks' sketches, merged, match one sketch of all.
+            let mut whole = HyperLogLog::new(12);
+            let mut merged = HyperLogLog::new(12);
+            for chunk in items.chunks(n / 8) {
+                let mut part = HyperLogLog::new(12);
+                for item in chunk {
+                    part.insert(item);
+                    whole.insert(item);
+                }
+                merged.merge(&HyperLogLog::decode(&part.encode()).unwrap());
+            }
+            assert_eq!(merged, whole);
+
+            let error = (whole.estimate() as f64 - exact).abs() / exact;
+            assert!(error < 0.05, "{} distinct, error {}", exact, error);
+        }
+    }
+
+    #[test]
+    fn count_min_never_underestimates() {
+        let items = generated_items(100_000, 4);
+        let mut exact = HashMap::<&str, u64>::new();
+        let mut sketch = CountMinSketch::new(2048, 4);
+        for item in items.iter() {
+            *exact.entry(item).or_default() += 1;
+            sketch.insert(item, 1);
+        }
+        let sketch = CountMinSketch::decode(&sketch.encode()).unwrap();
+
+        // e / width of the total count.
+        let bound = (std::f64::consts::E / 2048.0 * items.len() as f64) as u64;
+        let mut within = 0;
+        for (item, count) in exact.iter() {
+            let estimate = sketch.estimate(item);
+            assert!(estimate >= *count, "{}: {} < {}", item, estimate, count);
+            if estimate - count <= bound {
+                within += 1;
+            }
+        }
+        assert!(within as f64 >= 0.95 * exact.len() as f64);
+    }
+
+    #[test]
+    fn job_merges_map_side_sketches() {
+        let directory = PathBuf::from("./test-data/sketch_distinct_users");
+        let mut state: u64 = 11;
+        let mut exact = HashSet::new();
+        let input_files = (1..=3)
+            .map(|i| {
+                let users = (0..4000)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        let user = format!("user-{}", state % 5000);
+                        exact.insert(user.clone());
+                        format!("{}\n", user)
+                    })
+                    .collect::<String>();
+                let path = directory.join(format!("input_{}", i));
+                write(&path, users).unwrap();
+                path
+            })
+            .collect::<Vec<PathBuf>>();
+
+        let aggregate = Distinct { precision: 12 };
+        let keyed = Keyed {
+            combiner: Some(combiner(aggregate)),
+            ..Keyed::new(2)
+        };
+        let master = Master::new(
+            directory.clone(),
+            input_files.clone(),
+            keyed.map(Arc::new(move |contents: String| {
+                let mut sketch = aggregate.sketch();
+                for user in contents.lines() {
+                    sketch.insert(user);
+                }
+                vec![emit(&aggregate, "users", sketch)]
+            })),
+            keyed.reduce(reducer(aggregate)),
+        );
+
+        let results = master
+            .run(2)
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .collect::<Vec<KeyValue>>();
+        remove_job_outputs(&directory).unwrap();
+        for path in &input_files {
+            let _ = remove_file(path);
+        }
+
+        assert_eq!(results.len(), 1);
+        let estimate = results[0].value.parse::<f64>().unwrap();
+        let error = (estimate - exact.len() as f64).abs() / exact.len() as f64;
+        assert!(
+            error < 0.05,
+            "{} distinct, estimated {}",
+            exact.len(),
+            estimate
+        );
+    }
+}
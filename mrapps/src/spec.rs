@@ -0,0 +1,407 @@
+// Job specification files. A job is described in TOML, or in JSON when
+// the file name ends in `.json`:
+//
+//     app = "wc"
+//     inputs = ["data/*.txt", "more-data/"]
+//     n_reduce = 10
+//     combiner = true
+//     codec = "text"
+//     task_timeout_ms = 30000
+//     output_format = "json"
+//     working_directory = "out"
+//     map_cache = "cache"
+//     merge_output = true
+//
+// Relative paths are relative to the directory holding the spec file.
+// Globs and directories in `inputs` are expanded, directories recursively.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::{read_dir, read_to_string},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    keyed::{KeyedMap, KeyedReduce},
+    output::OutputFormat,
+    reducers::{combiner, reducer, Count},
+    terasort, wc,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartitionerKind {
+    #[default]
+    Hash,
+    /// Range partitioning on split points sampled from the inputs, so
+    /// the result files are in global key order.
+    Range,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobSpec {
+    pub app: String,
+    pub input_files: Vec<PathBuf>,
+    /// Zero makes a map-only job.
+    pub n_reduce: usize,
+    pub partitioner: PartitionerKind,
+    pub combiner: bool,
+    pub task_timeout: Option<Duration>,
+    pub max_attempts: Option<usize>,
+    pub output_format: OutputFormat,
+    pub working_directory: PathBuf,
+    /// Where to keep map outputs for reuse by later runs.
+    pub map_cache: Option<PathBuf>,
+    /// Merge the result files into one.
+    pub merge_output: bool,
+}
+
+// The file as written, before validation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSpec {
+    app: String,
+    inputs: Vec<String>,
+    n_reduce: Option<usize>,
+    partitioner: Option<String>,
+    combiner: Option<bool>,
+    codec: Option<String>,
+    task_timeout_ms: Option<u64>,
+    max_attempts: Option<usize>,
+    output_format: Option<String>,
+    working_directory: Option<PathBuf>,
+    map_cache: Option<PathBuf>,
+    merge_output: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum SpecError {
+    Io(PathBuf, io::Error),
+    /// The file is not valid TOML or JSON, or does not have the shape of a
+    /// spec. The message gives the position.
+    Parse(String),
+    /// A field has a value the job cannot use.
+    Field {
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SpecError::Parse(message) => write!(f, "{}", message),
+            SpecError::Field { field, message } => write!(f, "{}: {}", field, message),
+        }
+    }
+}
+
+impl Error for SpecError {}
+
+fn field_error(field: &str, message: String) -> SpecError {
+    SpecError::Field {
+        field: field.to_string(),
+        message,
+    }
+}
+
+fn unknown_app(app: &str) -> SpecError {
+    field_error(
+        "app",
+        format!("unknown app `{}`, expected one of {}", app, APPS.join(", ")),
+    )
+}
+
+/// The map and reduce functions of an app that jobs can name.
+pub(crate) struct App {
+    pub map: KeyedMap,
+    pub reduce: KeyedReduce,
+    pub combiner: Option<KeyedReduce>,
+    pub partitioner: PartitionerKind,
+}
+
+pub const APPS: &[&str] = &["wc", "terasort"];
+
+pub const DEFAULT_N_REDUCE: usize = 10;
+
+pub(crate) fn app(name: &str) -> Option<App> {
+    match name {
+        "wc" => Some(App {
+            map: Arc::new(wc::map),
+            reduce: reducer(Count),
+            combiner: Some(combiner(Count)),
+            partitioner: PartitionerKind::Hash,
+        }),
+        "terasort" => Some(App {
+            map: Arc::new(terasort::map),
+            reduce: Arc::new(terasort::reduce),
+            combiner: None,
+            partitioner: PartitionerKind::Range,
+        }),
+        _ => None,
+    }
+}
+
+impl JobSpec {
+    /// A spec for running `app` over `input_files` with the defaults a spec
+    /// file gets for the fields it leaves out.
+    pub fn new(app: &str, input_files: Vec<PathBuf>) -> Result<Self, SpecError> {
+        let Some(found) = self::app(app) else {
+            return Err(unknown_app(app));
+        };
+        Ok(JobSpec {
+            app: app.to_string(),
+            input_files,
+            n_reduce: DEFAULT_N_REDUCE,
+            partitioner: found.partitioner,
+            combiner: false,
+            task_timeout: None,
+            max_attempts: None,
+            output_format: OutputFormat::Text,
+            working_directory: PathBuf::from("."),
+            map_cache: None,
+            merge_output: false,
+        })
+    }
+
+    /// Reads a spec, as JSON if the name ends in `.json` and as TOML
+    /// otherwise.
+    pub fn from_file(path: &Path) -> Result<Self, SpecError> {
+        let text = read_to_string(path).map_err(|e| SpecError::Io(path.to_path_buf(), e))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            JobSpec::from_json(&text, base)
+        } else {
+            JobSpec::from_toml(&text, base)
+        }
+    }
+
+    /// Parses a TOML spec whose relative paths start at `base`.
+    pub fn from_toml(text: &str, base: &Path) -> Result<Self, SpecError> {
+        let raw = toml::from_str(text).map_err(|e| SpecError::Parse(e.to_string()))?;
+        JobSpec::validate(raw, base)
+    }
+
+    /// Parses a JSON spec whose relative paths start at `base`.
+    pub fn from_json(text: &str, base: &Path) -> Result<Self, SpecError> {
+        let raw = serde_json::from_str(text).map_err(|e| SpecError::Parse(e.to_string()))?;
+        JobSpec::validate(raw, base)
+    }
+
+    fn validate(raw: RawSpec, base: &Path) -> Result<Self, SpecError> {
+        let Some(app) = app(&raw.app) else {
+            return Err(unknown_app(&raw.app));
+        };
+
+        let n_reduce = raw.n_reduce.unwrap_or(DEFAULT_N_REDUCE);
+        let partitioner = match raw.partitioner.as_deref() {
+            None => app.partitioner,
+            Some("hash") => PartitionerKind::Hash,
+            Some("range") => PartitionerKind::Range,
+            Some(other) => {
+                return Err(field_error(
+                    "partitioner",
+                    format!("unknown partitioner `{}`, expected hash or range", other),
+                ))
+            }
+        };
+
+        let combiner = raw.combiner.unwrap_or(false);
+        if combiner && app.combiner.is_none() {
+            return Err(field_error(
+                "combiner",
+                format!("app `{}` has no combiner", raw.app),
+            ));
+        }
+        if combiner && n_reduce == 0 {
+            return Err(field_error(
+                "combiner",
+                "a map-only job has nothing to combine".to_string(),
+            ));
+        }
+
+        // Records are always written with the text encoding of `record`.
+        if let Some(codec) = raw.codec.filter(|codec| codec != "text") {
+            return Err(field_error(
+                "codec",
+                format!("unknown codec `{}`, expected text", codec),
+            ));
+        }
+
+        let task_timeout = match raw.task_timeout_ms {
+            Some(0) => {
+                return Err(field_error(
+                    "task_timeout_ms",
+                    "must be positive".to_string(),
+                ))
+            }
+            timeout => timeout.map(Duration::from_millis),
+        };
+        if raw.max_attempts == Some(0) {
+            return Err(field_error(
+                "max_attempts",
+                "must be at least 1".to_string(),
+            ));
+        }
+
+        let output_format = match raw.output_format.as_deref() {
+            None | Some("text") => OutputFormat::Text,
+            Some("json") => OutputFormat::JsonLines,
+            Some(other) => {
+                return Err(field_error(
+                    "output_format",
+                    format!("unknown output format `{}`, expected text or json", other),
+                ))
+            }
+        };
+
+        if raw.inputs.is_empty() {
+            return Err(field_error("inputs", "no inputs given".to_string()));
+        }
+        let mut input_files = vec![];
+        for (i, input) in raw.inputs.iter().enumerate() {
+            let files = expand_input(&base.join(input))
+                .map_err(|message| field_error(&format!("inputs[{}]", i), message))?;
+            if files.is_empty() {
+                return Err(field_error(
+                    &format!("inputs[{}]", i),
+                    format!("`{}` matches no files", input),
+                ));
+            }
+            input_files.extend(files);
+        }
+
+        Ok(JobSpec {
+            app: raw.app,
+            input_files,
+            n_reduce,
+            partitioner,
+            combiner,
+            task_timeout,
+            max_attempts: raw.max_attempts,
+            output_format,
+            working_directory: base.join(raw.working_directory.unwrap_or_default()),
+            map_cache: raw.map_cache.map(|directory| base.join(directory)),
+            merge_output: raw.merge_output.unwrap_or(false),
+        })
+    }
+}
+
+// A directory stands for every file below it, anything else is a glob.
+// Files come out sorted so map task ids are stable between runs.
+fn expand_input(pattern: &Path) -> Result<Vec<PathBuf>, String> {
+    if pattern.is_dir() {
+        let mut files = vec![];
+        walk(pattern, &mut files).map_err(|e| format!("{}: {}", pattern.display(), e))?;
+        files.sort();
+        return Ok(files);
+    }
+
+    let pattern = pattern.to_string_lossy();
+    let mut files = vec![];
+    for entry in glob::glob(&pattern).map_err(|e| format!("bad pattern: {}", e))? {
+        let path = entry.map_err(|e| e.to_string())?;
+        if path.is_dir() {
+            walk(&path, &mut files).map_err(|e| format!("{}: {}", path.display(), e))?;
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn walk(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRECTORY: &str = "test-data/spec_expands_inputs";
+
+    #[test]
+    fn spec_expands_inputs_and_reads_fields() {
+        let spec = JobSpec::from_file(&Path::new(DIRECTORY).join("job.toml")).unwrap();
+        let base = Path::new(DIRECTORY);
+        assert_eq!(
+            spec,
+            JobSpec {
+                app: "wc".to_string(),
+                input_files: vec![
+                    base.join("logs/a.txt"),
+                    base.join("logs/b.txt"),
+                    base.join("nested/d.txt"),
+                    base.join("nested/deeper/c.txt"),
+                ],
+                n_reduce: 3,
+                partitioner: PartitionerKind::Hash,
+                combiner: true,
+                task_timeout: Some(Duration::from_millis(2500)),
+                max_attempts: None,
+                output_format: OutputFormat::JsonLines,
+                working_directory: base.join("out"),
+                map_cache: None,
+                merge_output: true,
+            }
+        );
+
+        let json = JobSpec::from_file(&Path::new(DIRECTORY).join("job.json")).unwrap();
+        assert_eq!(json.app, "terasort");
+        assert_eq!(json.partitioner, PartitionerKind::Range);
+        assert_eq!(json.n_reduce, 0);
+    }
+
+    #[test]
+    fn spec_errors_name_the_field() {
+        let base = Path::new(DIRECTORY);
+        let field = |text: &str| match JobSpec::from_toml(text, base) {
+            Err(SpecError::Field { field, .. }) => field,
+            other => panic!("{:?}", other),
+        };
+
+        assert_eq!(field("app = 'grep'\ninputs = ['logs']"), "app");
+        assert_eq!(
+            field("app = 'wc'\ninputs = ['logs', 'missing/*']"),
+            "inputs[1]"
+        );
+        assert_eq!(
+            field("app = 'terasort'\ninputs = ['logs']\ncombiner = true"),
+            "combiner"
+        );
+        assert_eq!(
+            field("app = 'wc'\ninputs = ['logs']\ncodec = 'zstd'"),
+            "codec"
+        );
+        assert_eq!(
+            field("app = 'wc'\ninputs = ['logs']\noutput_format = 'xml'"),
+            "output_format"
+        );
+
+        let Err(SpecError::Parse(message)) =
+            JobSpec::from_toml("app = 'wc'\ninputs = ['logs']\nn_reduce = 'ten'", base)
+        else {
+            panic!("expected a parse error");
+        };
+        assert!(message.contains("n_reduce"), "{}", message);
+        assert!(JobSpec::from_json(r#"{"app": "wc", "inputs": [], "reduce": 2}"#, base).is_err());
+    }
+}
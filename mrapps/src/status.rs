@@ -0,0 +1,541 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::json_string;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskId {
+    Map(i32),
+    Reduce(i32),
+}
+
+impl TaskId {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TaskId::Map(_) => "map",
+            TaskId::Reduce(_) => "reduce",
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            TaskId::Map(i) | TaskId::Reduce(i) => *i,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Pending,
+    Map,
+    Reduce,
+    Done,
+    Cancelled,
+    /// The job stopped because the master could not go on.
+    Failed,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Pending => "pending",
+            Phase::Map => "map",
+            Phase::Reduce => "reduce",
+            Phase::Done => "done",
+            Phase::Cancelled => "cancelled",
+            Phase::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TaskState {
+    #[default]
+    Idle,
+    InProgress,
+    Done,
+    Failed,
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskState::Idle => "idle",
+            TaskState::InProgress => "in-progress",
+            TaskState::Done => "done",
+            TaskState::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskStatus {
+    pub id: TaskId,
+    pub state: TaskState,
+    pub worker: Option<usize>,
+    pub elapsed: Option<Duration>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub map_tasks: usize,
+    pub reduce_tasks: usize,
+    pub idle: usize,
+    pub in_progress: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub phase: Phase,
+    /// Why the job failed, when it did.
+    pub error: Option<String>,
+    pub elapsed: Duration,
+    pub tasks: Vec<TaskStatus>,
+    pub counters: Counters,
+}
+
+#[derive(Default)]
+struct Task {
+    state: TaskState,
+    worker: Option<usize>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    error: Option<String>,
+}
+
+struct Inner {
+    phase: Phase,
+    error: Option<String>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    tasks: BTreeMap<TaskId, Task>,
+}
+
+/// Shared view of a running job, updated by the master and its workers.
+#[derive(Clone)]
+pub struct JobStatus {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for JobStatus {
+    fn default() -> Self {
+        JobStatus {
+            inner: Arc::new(Mutex::new(Inner {
+                phase: Phase::Pending,
+                error: None,
+                started: None,
+                finished: None,
+                tasks: BTreeMap::new(),
+            })),
+        }
+    }
+}
+
+impl JobStatus {
+    pub fn set_phase(&self, phase: Phase) {
+        let mut inner = self.inner.lock().unwrap();
+        match phase {
+            Phase::Pending => {
+                inner.started = None;
+                inner.finished = None;
+                inner.error = None;
+                inner.tasks.clear();
+            }
+            Phase::Done | Phase::Cancelled | Phase::Failed => inner.finished = Some(Instant::now()),
+            _ => {
+                inner.started.get_or_insert_with(Instant::now);
+            }
+        }
+        inner.phase = phase;
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.inner.lock().unwrap().phase
+    }
+
+    /// Ends the job in `Phase::Failed` because of `error`.
+    pub fn fail(&self, error: &str) {
+        self.set_phase(Phase::Failed);
+        self.inner.lock().unwrap().error = Some(error.to_string());
+    }
+
+    pub fn task_idle(&self, id: TaskId) {
+        self.inner.lock().unwrap().tasks.insert(id, Task::default());
+    }
+
+    /// Marks a task in progress, unless another attempt already finished
+    /// it.
+    pub fn task_started(&self, id: TaskId, worker: usize) {
+        self.update(id, |task| {
+            if task.state == TaskState::Done {
+                return;
+            }
+            task.state = TaskState::InProgress;
+            task.worker = Some(worker);
+            task.started = Some(Instant::now());
+            task.finished = None;
+            task.error = None;
+        });
+    }
+
+    pub fn task_done(&self, id: TaskId) {
+        self.finish(id, TaskState::Done);
+    }
+
+    pub fn task_failed(&self, id: TaskId, error: &str) {
+        self.finish(id, TaskState::Failed);
+        self.update(id, |task| task.error = Some(error.to_string()));
+    }
+
+    fn finish(&self, id: TaskId, state: TaskState) {
+        self.update(id, |task| {
+            task.state = state;
+            task.finished = Some(Instant::now());
+        });
+    }
+
+    fn update<F: FnOnce(&mut Task)>(&self, id: TaskId, f: F) {
+        let mut inner = self.inner.lock().unwrap();
+        f(inner.tasks.entry(id).or_default());
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let mut counters = Counters::default();
+        let tasks = inner
+            .tasks
+            .iter()
+            .map(|(id, task)| {
+                match id {
+                    TaskId::Map(_) => counters.map_tasks += 1,
+                    TaskId::Reduce(_) => counters.reduce_tasks += 1,
+                }
+                match task.state {
+                    TaskState::Idle => counters.idle += 1,
+                    TaskState::InProgress => counters.in_progress += 1,
+                    TaskState::Done => counters.done += 1,
+                    TaskState::Failed => counters.failed += 1,
+                }
+                TaskStatus {
+                    id: *id,
+                    state: task.state,
+                    worker: task.worker,
+                    elapsed: task
+                        .started
+                        .map(|started| task.finished.unwrap_or(now) - started),
+                    error: task.error.clone(),
+                }
+            })
+            .collect();
+
+        Snapshot {
+            phase: inner.phase,
+            error: inner.error.clone(),
+            elapsed: inner
+                .started
+                .map(|started| inner.finished.unwrap_or(now) - started)
+                .unwrap_or_default(),
+            tasks,
+            counters,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        let tasks = self
+            .tasks
+            .iter()
+            .map(|task| {
+                format!(
+                    "{{\"kind\":\"{}\",\"id\":{},\"state\":\"{}\",\"worker\":{},\"elapsed_ms\":{},\"error\":{}}}",
+                    task.id.kind(),
+                    task.id.index(),
+                    task.state,
+                    task.worker
+                        .map(|w| w.to_string())
+                        .unwrap_or("null".to_string()),
+                    task.elapsed
+                        .map(|e| e.as_millis().to_string())
+                        .unwrap_or("null".to_string()),
+                    task.error
+                        .as_deref()
+                        .map(json_string)
+                        .unwrap_or("null".to_string()),
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let c = &self.counters;
+
+        format!(
+            "{{\"phase\":\"{}\",\"error\":{},\"elapsed_ms\":{},\"counters\":{{\"map_tasks\":{},\"reduce_tasks\":{},\"idle\":{},\"in_progress\":{},\"done\":{},\"failed\":{}}},\"tasks\":[{}]}}",
+            self.phase,
+            self.error
+                .as_deref()
+                .map(json_string)
+                .unwrap_or("null".to_string()),
+            self.elapsed.as_millis(),
+            c.map_tasks,
+            c.reduce_tasks,
+            c.idle,
+            c.in_progress,
+            c.done,
+            c.failed,
+            tasks
+        )
+    }
+
+    pub fn to_html(&self) -> String {
+        let rows = self
+            .tasks
+            .iter()
+            .map(|task| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    task.id.kind(),
+                    task.id.index(),
+                    task.state,
+                    task.worker.map(|w| w.to_string()).unwrap_or_default(),
+                    task.elapsed
+                        .map(|e| format!("{}ms", e.as_millis()))
+                        .unwrap_or_default(),
+                    task.error.as_deref().map(escape_html).unwrap_or_default(),
+                )
+            })
+            .collect::<String>();
+        let c = &self.counters;
+
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta http-equiv=\"refresh\" content=\"2\"><title>mrapps job</title></head><body>\
+             <h1>Phase: {}</h1>{}<p>Elapsed: {}ms</p>\
+             <p>idle {} / in-progress {} / done {} / failed {}</p>\
+             <table><tr><th>kind</th><th>id</th><th>state</th><th>worker</th><th>elapsed</th><th>error</th></tr>{}</table>\
+             </body></html>\n",
+            self.phase,
+            self.error
+                .as_deref()
+                .map(|error| format!("<p>Error: {}</p>", escape_html(error)))
+                .unwrap_or_default(),
+            self.elapsed.as_millis(),
+            c.idle,
+            c.in_progress,
+            c.done,
+            c.failed,
+            rows
+        )
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// How long a client may take to send its request or read the response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves a `JobStatus` over HTTP: `/status.json` for tools and `/` for people.
+/// Each connection is answered on its own thread, so a slow client holds
+/// up neither other clients nor the server's shutdown.
+pub struct StatusServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatusServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, status: JobStatus) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let status = status.clone();
+                    thread::spawn(move || {
+                        let _ = stream.set_read_timeout(Some(CLIENT_TIMEOUT));
+                        let _ = stream.set_write_timeout(Some(CLIENT_TIMEOUT));
+                        let _ = respond(stream, &status);
+                    });
+                }
+            }
+        });
+
+        Ok(StatusServer {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &JobStatus) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let (code, content_type, body) = match path {
+        "/status.json" => ("200 OK", "application/json", status.snapshot().to_json()),
+        "/" => ("200 OK", "text/html", status.snapshot().to_html()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn status_tracks_task_states() {
+        let status = JobStatus::default();
+        status.set_phase(Phase::Map);
+        status.task_idle(TaskId::Map(1));
+        status.task_idle(TaskId::Map(2));
+        status.task_idle(TaskId::Map(3));
+        status.task_started(TaskId::Map(1), 7);
+        status.task_started(TaskId::Map(2), 8);
+        status.task_done(TaskId::Map(1));
+        status.task_failed(TaskId::Map(2), "exit status: 1");
+
+        let snapshot = status.snapshot();
+
+        assert_eq!(snapshot.phase, Phase::Map);
+        assert_eq!(
+            snapshot.counters,
+            Counters {
+                map_tasks: 3,
+                reduce_tasks: 0,
+                idle: 1,
+                in_progress: 0,
+                done: 1,
+                failed: 1,
+            }
+        );
+        assert_eq!(snapshot.tasks[0].worker, Some(7));
+        assert_eq!(snapshot.tasks[1].state, TaskState::Failed);
+        assert_eq!(snapshot.tasks[1].error.as_deref(), Some("exit status: 1"));
+        assert_eq!(snapshot.tasks[2].elapsed, None);
+    }
+
+    #[test]
+    fn server_serves_json_and_html() {
+        let status = JobStatus::default();
+        status.set_phase(Phase::Reduce);
+        status.task_idle(TaskId::Reduce(2));
+        status.task_started(TaskId::Reduce(2), 0);
+
+        let server = StatusServer::bind("127.0.0.1:0", status).unwrap();
+
+        let json = get(server.local_addr(), "/status.json");
+        assert!(json.starts_with("HTTP/1.1 200 OK"));
+        assert!(json.contains("\"phase\":\"reduce\""));
+        assert!(
+            json.contains("{\"kind\":\"reduce\",\"id\":2,\"state\":\"in-progress\",\"worker\":0,")
+        );
+
+        let html = get(server.local_addr(), "/");
+        assert!(html.contains("text/html"));
+        assert!(html.contains("<h1>Phase: reduce</h1>"));
+
+        assert!(get(server.local_addr(), "/nope").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn idle_client_does_not_stall_server() {
+        let server = StatusServer::bind("127.0.0.1:0", JobStatus::default()).unwrap();
+        let _idle = TcpStream::connect(server.local_addr()).unwrap();
+
+        let start = Instant::now();
+        assert!(get(server.local_addr(), "/status.json").starts_with("HTTP/1.1 200 OK"));
+        drop(server);
+        assert!(start.elapsed() < CLIENT_TIMEOUT);
+    }
+
+    #[test]
+    fn cancelled_job_stops_the_clock() {
+        let status = JobStatus::default();
+        status.set_phase(Phase::Map);
+        status.set_phase(Phase::Cancelled);
+        let elapsed = status.snapshot().elapsed;
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(status.snapshot().elapsed, elapsed);
+    }
+
+    #[test]
+    fn failed_job_reports_its_error() {
+        let status = JobStatus::default();
+        status.set_phase(Phase::Map);
+        status.fail("write-ahead log: disk full");
+
+        let snapshot = status.snapshot();
+        assert_eq!(snapshot.phase, Phase::Failed);
+        assert!(snapshot
+            .to_json()
+            .starts_with("{\"phase\":\"failed\",\"error\":\"write-ahead log: disk full\","));
+        assert!(snapshot
+            .to_html()
+            .contains("<p>Error: write-ahead log: disk full</p>"));
+
+        status.set_phase(Phase::Pending);
+        assert_eq!(status.snapshot().error, None);
+    }
+}
@@ -0,0 +1,224 @@
+// Hadoop-streaming style jobs: map and reduce tasks run an external program,
+// write the task input to its stdin and read "key<TAB>value" lines from its
+// stdout. A program that cannot be started or exits unsuccessfully fails
+// the task, with its stderr as the error reported to the master.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    process::{ChildStdin, Command, Stdio},
+    sync::Arc,
+    thread,
+};
+
+use crate::{
+    keyed::{records, Keyed, Merge},
+    record::encode,
+    worker::{fail_task, KeyValue, MapFn},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Executable {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Executable {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Executable {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    /// Runs `script` with `sh -c`.
+    pub fn shell(script: &str) -> Self {
+        Executable::new("sh", &["-c", script])
+    }
+
+    /// Runs the program, feeding its stdin from `input` on another thread
+    /// so a chatty program cannot deadlock against us, and returns its
+    /// stdout. The error says why the program could not run or, when it
+    /// did not succeed, how it exited and what it wrote to stderr.
+    pub fn run<F>(&self, input: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut ChildStdin) + Send + 'static,
+    {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not start `{}`: {}", self.program, e))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || input(&mut stdin));
+        let output = child.wait_with_output();
+        let _ = writer.join();
+        let output = output.map_err(|e| format!("could not run `{}`: {}", self.program, e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "`{}` failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Splits a streaming output line at its first tab. A line without a tab
+/// is all key.
+pub fn parse_line(line: &str) -> KeyValue {
+    let (key, value) = line.split_once('\t').unwrap_or((line, ""));
+    KeyValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// A map function piping each input split through `mapper`. Its output is
+/// partitioned and sorted like any other keyed map output. A mapper that
+/// fails fails the task with its error.
+pub fn map(keyed: &Keyed, mapper: Executable) -> MapFn {
+    let keyed = keyed.clone();
+    Arc::new(move |mut input: BufReader<File>| {
+        let mut contents = vec![];
+        if let Err(e) = input.read_to_end(&mut contents) {
+            fail_task(e);
+        }
+        let stdout = mapper
+            .run(move |stdin| {
+                // The program may exit without reading everything.
+                let _ = stdin.write_all(&contents);
+            })
+            .unwrap_or_else(|e| fail_task(e));
+        keyed.partition(stdout.lines().map(parse_line).collect())
+    })
+}
+
+/// A reduce function feeding the merged, sorted "key<TAB>value" lines of
+/// its partition to `reducer`, which sees every key's lines together. A
+/// reducer that fails fails the task with its error.
+pub fn reduce(
+    keyed: &Keyed,
+    reducer: Executable,
+) -> Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync> {
+    let keyed = keyed.clone();
+    Arc::new(move |inputs: Vec<BufReader<File>>| {
+        let sources = inputs.into_iter().map(records).collect();
+        let merged = Merge::new(sources, keyed.sort.clone());
+        let stdout = reducer
+            .run(move |stdin| {
+                for kv in merged {
+                    if writeln!(stdin, "{}\t{}", kv.key, kv.value).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap_or_else(|e| fail_task(e));
+        stdout
+            .lines()
+            .map(|line| format!("{}\n", encode(&parse_line(line))))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::read_to_string, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        keyed::read_results, master::Master, pipeline::remove_job_outputs, status::TaskState,
+    };
+
+    #[test]
+    fn streaming_word_count() {
+        let working_directory = PathBuf::from("./test-data/streaming_word_count");
+        let input_files = ["input_1", "input_2"]
+            .into_iter()
+            .map(|filename| working_directory.join(filename))
+            .collect::<Vec<PathBuf>>();
+        let keyed = Keyed::new(2);
+        let master = Master::new(
+            working_directory.clone(),
+            input_files,
+            map(
+                &keyed,
+                Executable::shell("tr -s ' ' '\\n' | awk 'NF { print $1 \"\\t1\" }'"),
+            ),
+            reduce(
+                &keyed,
+                Executable::new(
+                    "awk",
+                    &[
+                        "-F",
+                        "\t",
+                        "{ c[$1] += $2 } END { for (k in c) print k \"\\t\" c[k] }",
+                    ],
+                ),
+            ),
+        );
+
+        let mut counts = master
+            .run(2)
+            .iter()
+            .flat_map(|path| read_results(&read_to_string(path).unwrap()))
+            .map(|kv| (kv.key, kv.value))
+            .collect::<Vec<(String, String)>>();
+        counts.sort();
+
+        assert_eq!(
+            counts,
+            vec![
+                ("a".to_string(), "3".to_string()),
+                ("b".to_string(), "2".to_string()),
+                ("c".to_string(), "1".to_string()),
+            ]
+        );
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn failing_program_fails_the_task() {
+        let working_directory = PathBuf::from("./test-data/streaming_failing_program");
+        let keyed = Keyed::new(1);
+        let master = Master::new(
+            working_directory.clone(),
+            vec![working_directory.join("input_1")],
+            map(
+                &keyed,
+                Executable::shell("cat > /dev/null; echo 'bad record' >&2; exit 3"),
+            ),
+            reduce(&keyed, Executable::new("cat", &[])),
+        );
+
+        master.run(1);
+
+        let snapshot = master.status().snapshot();
+        let task = &snapshot.tasks[0];
+        assert_eq!(task.state, TaskState::Failed);
+        let error = task.error.as_deref().unwrap();
+        assert!(error.contains("exit status: 3"), "{}", error);
+        assert!(error.contains("bad record"), "{}", error);
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn run_returns_program_failure() {
+        let error = Executable::shell("echo oops >&2; exit 3")
+            .run(|_| {})
+            .unwrap_err();
+        assert!(error.contains("exit status: 3"), "{}", error);
+        assert!(error.contains("oops"), "{}", error);
+
+        let error = Executable::new("./no-such-program", &[])
+            .run(|_| {})
+            .unwrap_err();
+        assert!(error.starts_with("could not start"), "{}", error);
+    }
+}
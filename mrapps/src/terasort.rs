@@ -0,0 +1,244 @@
+// TeraSort: sorts records so that reading reduce.1.result through
+// reduce.N.result in order yields one globally sorted dataset. Each input
+// line is a record; a tab separates its key from its value.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    keyed::Keyed,
+    master::Master,
+    output::partition_index,
+    partition::range_partitioner,
+    record::{decode, Records},
+    worker::KeyValue,
+};
+
+pub const SAMPLES_PER_FILE: usize = 1000;
+
+fn parse(line: &str) -> KeyValue {
+    decode(line).unwrap_or(KeyValue {
+        key: line.to_string(),
+        value: String::new(),
+    })
+}
+
+pub fn map(contents: String) -> Vec<KeyValue> {
+    contents.lines().map(parse).collect()
+}
+
+pub fn reduce(_key: &str, records: &mut dyn Iterator<Item = KeyValue>) -> Vec<KeyValue> {
+    records.collect()
+}
+
+/// Samples up to `samples_per_file` keys from every input and returns the
+/// `n_reduce - 1` keys that split the sample into equally sized ranges.
+pub fn split_points(
+    input_files: &[PathBuf],
+    n_reduce: usize,
+    samples_per_file: usize,
+) -> io::Result<Vec<String>> {
+    let mut samples = vec![];
+    for input in input_files {
+        samples.extend(sample_keys(input, samples_per_file)?);
+    }
+    samples.sort();
+
+    if samples.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok((1..n_reduce)
+        .map(|i| samples[i * samples.len() / n_reduce].clone())
+        .collect())
+}
+
+// Reservoir sampling with a fixed seed, so the same input always gives the
+// same split points.
+fn sample_keys(path: &Path, k: usize) -> io::Result<Vec<String>> {
+    let mut reservoir = Vec::with_capacity(k);
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    for (seen, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let key = parse(&line?).key;
+        if reservoir.len() < k {
+            reservoir.push(key);
+            continue;
+        }
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let slot = (state % (seen as u64 + 1)) as usize;
+        if slot < k {
+            reservoir[slot] = key;
+        }
+    }
+    Ok(reservoir)
+}
+
+/// Samples the inputs, then sorts them with `n_reduce` range partitioned
+/// reduce tasks. Returns the result files in partition order.
+pub fn run(
+    working_directory: PathBuf,
+    input_files: Vec<PathBuf>,
+    n_reduce: usize,
+    n_workers: i32,
+) -> io::Result<Vec<PathBuf>> {
+    let split_points = split_points(&input_files, n_reduce, SAMPLES_PER_FILE)?;
+    let keyed = Keyed {
+        partitioner: range_partitioner(split_points),
+        ..Keyed::new(n_reduce)
+    };
+    let master = Master::new(
+        working_directory,
+        input_files,
+        keyed.map(Arc::new(map)),
+        keyed.reduce(Arc::new(reduce)),
+    );
+
+    let mut result_files = master.run(n_workers);
+    result_files.sort_by_key(|path| partition_index(path));
+    Ok(result_files)
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    OutOfOrder {
+        file: PathBuf,
+        record: usize,
+        previous: String,
+        key: String,
+    },
+    RecordCount {
+        expected: usize,
+        found: usize,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::OutOfOrder {
+                file,
+                record,
+                previous,
+                key,
+            } => write!(
+                f,
+                "{} record {}: key {:?} sorts before previous key {:?}",
+                file.display(),
+                record,
+                key,
+                previous
+            ),
+            ValidationError::RecordCount { expected, found } => {
+                write!(f, "expected {} records, found {}", expected, found)
+            }
+            ValidationError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl From<io::Error> for ValidationError {
+    fn from(e: io::Error) -> Self {
+        ValidationError::Io(e)
+    }
+}
+
+pub fn count_records(files: &[PathBuf]) -> io::Result<usize> {
+    let mut count = 0;
+    for path in files {
+        count += BufReader::new(File::open(path)?).lines().count();
+    }
+    Ok(count)
+}
+
+/// Checks that the concatenation of `result_files` is sorted by key and
+/// holds exactly `expected_records` records.
+pub fn validate(result_files: &[PathBuf], expected_records: usize) -> Result<(), ValidationError> {
+    let mut previous: Option<String> = None;
+    let mut found = 0;
+    for path in result_files {
+        for (record, kv) in Records::new(BufReader::new(File::open(path)?)).enumerate() {
+            let kv = kv?;
+            if let Some(previous) = previous.filter(|p| *p > kv.key) {
+                return Err(ValidationError::OutOfOrder {
+                    file: path.clone(),
+                    record: record + 1,
+                    previous,
+                    key: kv.key,
+                });
+            }
+            previous = Some(kv.key);
+            found += 1;
+        }
+    }
+
+    if found != expected_records {
+        return Err(ValidationError::RecordCount {
+            expected: expected_records,
+            found,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+
+    use super::*;
+    use crate::pipeline::remove_job_outputs;
+
+    #[test]
+    fn terasort_sorts_globally() {
+        let working_directory = PathBuf::from("./test-data/terasort_sorts_globally");
+        let mut state: u32 = 7;
+        let input_files = (1..=3)
+            .map(|i| {
+                let records = (0..500)
+                    .map(|n| {
+                        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                        format!("{:08x}\tinput {} record {}\n", state, i, n)
+                    })
+                    .collect::<String>();
+                let path = working_directory.join(format!("input_{}", i));
+                write(&path, records).unwrap();
+                path
+            })
+            .collect::<Vec<PathBuf>>();
+        let expected = count_records(&input_files).unwrap();
+
+        let result_files = run(working_directory.clone(), input_files.clone(), 4, 3).unwrap();
+
+        assert_eq!(result_files.len(), 4);
+        validate(&result_files, expected).unwrap();
+        let mut sizes = result_files
+            .iter()
+            .map(|path| count_records(std::slice::from_ref(path)).unwrap());
+        assert!(sizes.all(|size| size > 0));
+
+        let mut reversed = result_files.clone();
+        reversed.reverse();
+        assert!(matches!(
+            validate(&reversed, expected),
+            Err(ValidationError::OutOfOrder { .. })
+        ));
+        assert!(matches!(
+            validate(&result_files, expected + 1),
+            Err(ValidationError::RecordCount { .. })
+        ));
+
+        remove_job_outputs(&working_directory).unwrap();
+        for path in input_files {
+            let _ = remove_file(path);
+        }
+    }
+}
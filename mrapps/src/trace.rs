@@ -0,0 +1,328 @@
+// Structured events for the life of every task, sent to a pluggable sink.
+// The master records dispatches, retries and timeouts; workers record when
+// an attempt starts and how it ends, with its duration and the bytes it
+// read and wrote. `chrome_trace` turns the events into a timeline for
+// chrome://tracing or Perfetto.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::status::TaskId;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Microseconds since tracing started.
+    pub at_us: u64,
+    pub task: TaskId,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    Dispatched {
+        attempt: usize,
+    },
+    /// Dispatched again after a failure or a timeout.
+    Retried {
+        attempt: usize,
+    },
+    Started {
+        worker: usize,
+    },
+    Finished {
+        worker: usize,
+        duration_us: u64,
+        bytes_read: u64,
+        bytes_written: u64,
+    },
+    /// An attempt failed on a worker, or timed out when there is none.
+    Failed {
+        worker: Option<usize>,
+        duration_us: u64,
+        error: String,
+    },
+}
+
+pub trait Sink: Send + Sync {
+    fn record(&self, event: &Event);
+}
+
+/// Stamps events with the time since it was created and hands them to a
+/// sink. Clones share the sink and the start time.
+#[derive(Clone)]
+pub struct Tracer {
+    sink: Arc<dyn Sink>,
+    started: Instant,
+}
+
+impl Tracer {
+    pub fn new(sink: Arc<dyn Sink>) -> Self {
+        Tracer {
+            sink,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn emit(&self, task: TaskId, kind: EventKind) {
+        self.sink.record(&Event {
+            at_us: micros(self.started.elapsed()),
+            task,
+            kind,
+        });
+    }
+}
+
+pub(crate) fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+/// Keeps events in memory.
+#[derive(Default)]
+pub struct MemorySink {
+    events: Mutex<Vec<Event>>,
+}
+
+impl MemorySink {
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Sink for MemorySink {
+    fn record(&self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Appends one JSON object per event to a file, readable with
+/// `read_events` while the job is still running.
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(JsonLinesSink {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+}
+
+impl Sink for JsonLinesSink {
+    fn record(&self, event: &Event) {
+        let mut line = serde_json::to_string(event).unwrap();
+        line.push('\n');
+        // Tracing must not fail the job.
+        let _ = self.file.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+/// Reads the events a `JsonLinesSink` wrote, skipping a torn last line.
+pub fn read_events(path: &Path) -> io::Result<Vec<Event>> {
+    let mut events = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        events.extend(serde_json::from_str(&line?).ok());
+    }
+    Ok(events)
+}
+
+/// Renders events in the Chrome trace event format. Each attempt is a
+/// span on its worker's track; dispatches, retries and timeouts are
+/// instants on the master's track.
+pub fn chrome_trace(events: &[Event]) -> String {
+    let name = |task: &TaskId| format!("{} {}", task.kind(), task.index());
+    let track = |worker: Option<usize>| worker.map_or(0, |worker| worker + 1);
+
+    let mut workers = vec![];
+    let mut trace = vec![];
+    for event in events {
+        match &event.kind {
+            EventKind::Started { .. } => {}
+            EventKind::Dispatched { attempt } | EventKind::Retried { attempt } => {
+                let what = match event.kind {
+                    EventKind::Dispatched { .. } => "dispatch",
+                    _ => "retry",
+                };
+                trace.push(json!({
+                    "name": format!("{} {}", what, name(&event.task)),
+                    "cat": event.task.kind(),
+                    "ph": "i",
+                    "s": "t",
+                    "ts": event.at_us,
+                    "pid": 1,
+                    "tid": 0,
+                    "args": { "attempt": attempt },
+                }));
+            }
+            EventKind::Finished {
+                worker,
+                duration_us,
+                bytes_read,
+                bytes_written,
+            } => {
+                workers.push(*worker);
+                trace.push(json!({
+                    "name": name(&event.task),
+                    "cat": event.task.kind(),
+                    "ph": "X",
+                    "ts": event.at_us.saturating_sub(*duration_us),
+                    "dur": duration_us,
+                    "pid": 1,
+                    "tid": track(Some(*worker)),
+                    "args": { "bytes_read": bytes_read, "bytes_written": bytes_written },
+                }));
+            }
+            EventKind::Failed {
+                worker,
+                duration_us,
+                error,
+            } => {
+                workers.extend(worker);
+                trace.push(json!({
+                    "name": format!("{} (failed)", name(&event.task)),
+                    "cat": event.task.kind(),
+                    "ph": "X",
+                    "ts": event.at_us.saturating_sub(*duration_us),
+                    "dur": duration_us,
+                    "pid": 1,
+                    "tid": track(*worker),
+                    "args": { "error": error },
+                }));
+            }
+        }
+    }
+
+    workers.sort();
+    workers.dedup();
+    let thread_name = |tid: usize, name: String| {
+        json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": tid,
+            "args": { "name": name },
+        })
+    };
+    let mut names = vec![thread_name(0, "master".to_string())];
+    names.extend(
+        workers
+            .into_iter()
+            .map(|worker| thread_name(track(Some(worker)), format!("worker {}", worker))),
+    );
+    names.extend(trace);
+
+    json!({ "traceEvents": Value::Array(names) }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{metadata, read_to_string, remove_file},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::{keyed::Keyed, master::Master, pipeline::remove_job_outputs, worker::KeyValue};
+
+    #[test]
+    fn job_records_task_lifecycle() {
+        let directory = PathBuf::from("./test-data/trace_task_lifecycle");
+        let input_files = (1..=2)
+            .map(|i| directory.join(format!("input_{}", i)))
+            .collect::<Vec<PathBuf>>();
+        let keyed = Keyed::new(2);
+        let mut master = Master::new(
+            directory.clone(),
+            input_files.clone(),
+            keyed.map(Arc::new(|contents: String| {
+                contents
+                    .split_whitespace()
+                    .map(|word| KeyValue {
+                        key: word.to_string(),
+                        value: "1".to_string(),
+                    })
+                    .collect()
+            })),
+            keyed.reduce(Arc::new(|key, values| {
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: values.count().to_string(),
+                }]
+            })),
+        );
+        let sink = Arc::new(MemorySink::default());
+        master.set_trace_sink(sink.clone());
+        master.run(2);
+        remove_job_outputs(&directory).unwrap();
+
+        let events = sink.events();
+        let tasks = [
+            TaskId::Map(1),
+            TaskId::Map(2),
+            TaskId::Reduce(1),
+            TaskId::Reduce(2),
+        ];
+        for task in tasks {
+            let kinds = events
+                .iter()
+                .filter(|event| event.task == task)
+                .map(|event| &event.kind)
+                .collect::<Vec<&EventKind>>();
+            assert!(
+                matches!(
+                    kinds.as_slice(),
+                    [
+                        EventKind::Dispatched { attempt: 1 },
+                        EventKind::Started { .. },
+                        EventKind::Finished { bytes_written, .. },
+                    ] if *bytes_written > 0
+                ),
+                "{:?}: {:?}",
+                task,
+                kinds
+            );
+        }
+        for (i, input) in input_files.iter().enumerate() {
+            let read = events.iter().find_map(|event| match event.kind {
+                EventKind::Finished { bytes_read, .. }
+                    if event.task == TaskId::Map(i as i32 + 1) =>
+                {
+                    Some(bytes_read)
+                }
+                _ => None,
+            });
+            assert_eq!(read, Some(metadata(input).unwrap().len()));
+        }
+
+        let trace: Value = serde_json::from_str(&chrome_trace(&events)).unwrap();
+        let spans = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .count();
+        assert_eq!(spans, tasks.len());
+
+        // The same events survive a round trip through a JSON lines file.
+        let path = directory.join("events.jsonl");
+        let file_sink = JsonLinesSink::create(&path).unwrap();
+        for event in events.iter() {
+            file_sink.record(event);
+        }
+        assert!(read_to_string(&path)
+            .unwrap()
+            .contains(r#""event":"finished""#));
+        assert_eq!(read_events(&path).unwrap(), events);
+        remove_file(path).unwrap();
+    }
+}
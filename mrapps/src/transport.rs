@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use chan::{chan_select, Receiver};
+
+use crate::worker::{Job, JobResult};
+
+/// What waiting for a result turned up.
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Result(JobResult),
+    /// The deadline passed first.
+    TimedOut,
+    /// No result can arrive any more.
+    Closed,
+}
+
+/// Carries jobs from the master to its workers and results back, so the
+/// master's scheduling runs the same over real threads or a simulation.
+pub trait Transport {
+    fn send(&mut self, job: Job);
+
+    /// Waits for the next result, giving up at `deadline` if there is one.
+    fn recv(&mut self, deadline: Option<Duration>) -> Received;
+
+    /// Time since the run started.
+    fn now(&self) -> Duration;
+}
+
+/// Worker threads fed through channels.
+pub struct Channels<'a> {
+    pub send: &'a dyn Fn(Job),
+    pub results_queue: &'a Receiver<JobResult>,
+    pub started: Instant,
+}
+
+impl Transport for Channels<'_> {
+    fn send(&mut self, job: Job) {
+        (self.send)(job)
+    }
+
+    fn recv(&mut self, deadline: Option<Duration>) -> Received {
+        let results_queue = self.results_queue;
+        let received =
+            |result: Option<JobResult>| result.map_or(Received::Closed, Received::Result);
+        let Some(deadline) = deadline else {
+            return received(results_queue.recv());
+        };
+
+        let timeout = chan::after(deadline.saturating_sub(self.now()));
+        let mut outcome = Received::TimedOut;
+        chan_select! {
+            timeout.recv() => {},
+            results_queue.recv() -> result => outcome = received(result),
+        }
+        outcome
+    }
+
+    fn now(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
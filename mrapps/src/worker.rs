@@ -1,58 +1,205 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufReader, Write},
-    path::PathBuf,
-    sync::Arc,
+    any::Any,
+    collections::HashMap,
+    fmt,
+    fs::{metadata, remove_file, rename, File, OpenOptions},
+    io::{self, BufReader, Write},
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 use chan::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checkpoint::committed_outputs,
+    isolation::{run_isolated, Execution},
+    output::OutputFormat,
+    retry::RetryPolicy,
+    shuffle::MemoryShuffle,
+    status::{JobStatus, TaskId},
+    trace::{micros, EventKind, Tracer},
+};
+
+pub type MapFn = Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct KeyValue {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Job {
     Map((i32, PathBuf)),
     Reduce((i32, Vec<PathBuf>)),
+    /// A map task of a job without reduce tasks; its output is final.
+    MapOnly((i32, PathBuf)),
+}
+
+impl Job {
+    pub fn task_id(&self) -> TaskId {
+        match self {
+            Job::Map((job_id, _)) | Job::MapOnly((job_id, _)) => TaskId::Map(*job_id),
+            Job::Reduce((job_id, _)) => TaskId::Reduce(*job_id),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum JobResult {
     MapFinished(i32),
     ReduceFinished(i32),
+    MapFailed(i32, String),
+    ReduceFailed(i32, String),
+    /// The job was cancelled before the task started.
+    Cancelled(TaskId),
 }
 
 pub struct Worker {
     pub working_directory: PathBuf,
     pub map: Arc<dyn Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
+    /// Map functions for specific map jobs, used instead of `map`.
+    pub input_maps: Arc<HashMap<i32, MapFn>>,
     pub reduce: Arc<dyn Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
-    pub job_queue: Receiver<Job>,
-    pub results_queue: Sender<JobResult>,
+    pub status: JobStatus,
+    pub output_format: OutputFormat,
+    pub execution: Execution,
+    /// Keeps map output in memory instead of the working directory.
+    pub shuffle: Option<MemoryShuffle>,
+    /// Set when the job is cancelled; queued jobs are then abandoned.
+    pub cancelled: Arc<AtomicBool>,
+    pub tracer: Option<Tracer>,
+    /// Retries transient errors opening inputs and writing outputs.
+    pub retry: RetryPolicy,
 }
 
 impl Worker {
-    pub fn run(&self) {
-        for job in self.job_queue.iter() {
-            match job {
-                Job::Map((job_id, path)) => {
-                    let results = (self.map)(open_file(path));
-                    let names = self.map_result_names(&job_id, results.len());
-                    self.write_map_results(names, results);
-                    self.results_queue.send(JobResult::MapFinished(job_id));
+    pub fn run(&self, id: usize, job_queue: Receiver<Job>, results_queue: Sender<JobResult>) {
+        for job in job_queue.iter() {
+            results_queue.send(self.process(id, job));
+        }
+    }
+
+    /// Runs one job as worker `id`.
+    pub fn process(&self, id: usize, job: Job) -> JobResult {
+        let task = job.task_id();
+        if self.cancelled.load(Ordering::SeqCst) {
+            return JobResult::Cancelled(task);
+        }
+        self.status.task_started(task, id);
+        let started = Instant::now();
+        let bytes_read = match &self.tracer {
+            Some(tracer) => {
+                tracer.emit(task, EventKind::Started { worker: id });
+                self.bytes_read(&job)
+            }
+            None => 0,
+        };
+
+        // A panicking task, or one whose I/O keeps failing, fails the task,
+        // not the worker.
+        let outcome = match &self.execution {
+            Execution::Thread => catch_unwind(AssertUnwindSafe(|| self.execute(job)))
+                .map_err(panic_message)
+                .and_then(|result| result.map_err(|e| e.to_string())),
+            Execution::Process(limits) => run_isolated(limits, || {
+                if let Err(e) = self.execute(job) {
+                    panic!("{}", e);
                 }
-                Job::Reduce((job_id, paths)) => {
-                    let files = paths
-                        .into_iter()
-                        .map(|path| open_file(path))
-                        .collect::<Vec<BufReader<File>>>();
-                    let result = (self.reduce)(files);
-                    let name = self.reduce_result_name(&job_id);
-                    self.write_reduce_results(name, result);
-                    self.results_queue.send(JobResult::ReduceFinished(job_id));
+            }),
+        };
+
+        if let Some(tracer) = &self.tracer {
+            let duration_us = micros(started.elapsed());
+            let kind = match &outcome {
+                Ok(()) => EventKind::Finished {
+                    worker: id,
+                    duration_us,
+                    bytes_read,
+                    bytes_written: self.bytes_written(task),
+                },
+                Err(error) => EventKind::Failed {
+                    worker: Some(id),
+                    duration_us,
+                    error: error.clone(),
+                },
+            };
+            tracer.emit(task, kind);
+        }
+        match (task, outcome) {
+            (TaskId::Map(job_id), Ok(())) => JobResult::MapFinished(job_id),
+            (TaskId::Reduce(job_id), Ok(())) => JobResult::ReduceFinished(job_id),
+            (TaskId::Map(job_id), Err(error)) => JobResult::MapFailed(job_id, error),
+            (TaskId::Reduce(job_id), Err(error)) => JobResult::ReduceFailed(job_id, error),
+        }
+    }
+
+    fn execute(&self, job: Job) -> io::Result<()> {
+        match job {
+            Job::Map((job_id, path)) => {
+                let map = self.input_maps.get(&job_id).unwrap_or(&self.map);
+                let results = map(self.open_file(&path)?);
+                match &self.shuffle {
+                    Some(shuffle) => self.retry.run(|| shuffle.write(job_id, &results)),
+                    None => {
+                        let names = self.map_result_names(&job_id, results.len());
+                        self.write_map_results(names, results)
+                    }
                 }
             }
+            Job::MapOnly((job_id, path)) => {
+                let map = self.input_maps.get(&job_id).unwrap_or(&self.map);
+                let results = map(self.open_file(&path)?);
+                let name = self.map_only_result_name(&job_id);
+                self.write_final_results(name, results.concat())
+            }
+            Job::Reduce((job_id, paths)) => {
+                let files = match &self.shuffle {
+                    Some(shuffle) => self.retry.run(|| shuffle.readers(job_id))?,
+                    None => paths
+                        .iter()
+                        .map(|path| self.open_file(path))
+                        .collect::<io::Result<Vec<BufReader<File>>>>()?,
+                };
+                let result = (self.reduce)(files);
+                let name = self.reduce_result_name(&job_id);
+                self.write_final_results(name, result)
+            }
+        }
+    }
+
+    fn open_file(&self, path: &Path) -> io::Result<BufReader<File>> {
+        self.retry
+            .run(|| OpenOptions::new().read(true).open(path))
+            .map(BufReader::new)
+            .map_err(|e| in_file(path, e))
+    }
+
+    fn bytes_read(&self, job: &Job) -> u64 {
+        let size = |path: &PathBuf| metadata(path).map_or(0, |metadata| metadata.len());
+        match (job, &self.shuffle) {
+            (Job::Reduce((job_id, _)), Some(shuffle)) => shuffle.bytes(TaskId::Reduce(*job_id)),
+            (Job::Reduce((_, paths)), None) => paths.iter().map(size).sum(),
+            (Job::Map((_, path)) | Job::MapOnly((_, path)), _) => size(path),
+        }
+    }
+
+    fn bytes_written(&self, task: TaskId) -> u64 {
+        match (task, &self.shuffle) {
+            (TaskId::Map(_), Some(shuffle)) => shuffle.bytes(task),
+            _ => committed_outputs(&self.working_directory, task)
+                .iter()
+                .map(|(_, len)| len)
+                .sum(),
         }
     }
 
@@ -66,11 +213,18 @@ impl Worker {
             .collect()
     }
 
-    fn write_map_results(&self, names: Vec<PathBuf>, results: Vec<String>) {
-        for (filename, result) in names.iter().zip(results.into_iter()) {
-            let mut f = File::create(filename).unwrap();
-            let _ = f.write_all(result.as_bytes());
+    fn write_map_results(&self, names: Vec<PathBuf>, results: Vec<String>) -> io::Result<()> {
+        for (filename, result) in names.iter().zip(results) {
+            self.retry
+                .run(|| {
+                    write_atomically(filename, |path| {
+                        let mut f = File::create(path)?;
+                        f.write_all(result.as_bytes())
+                    })
+                })
+                .map_err(|e| in_file(filename, e))?;
         }
+        Ok(())
     }
 
     fn reduce_result_name(&self, job_id: &i32) -> PathBuf {
@@ -79,34 +233,151 @@ impl Worker {
         path
     }
 
-    fn write_reduce_results(&self, name: PathBuf, result: String) {
-        let mut f = File::create(name).unwrap();
-        let _ = f.write_all(&result.as_bytes());
+    fn map_only_result_name(&self, job_id: &i32) -> PathBuf {
+        let mut path = self.working_directory.clone();
+        path.push(format!("map.{}.result", job_id));
+        path
+    }
+
+    fn write_final_results(&self, name: PathBuf, result: String) -> io::Result<()> {
+        self.retry
+            .run(|| write_atomically(&name, |path| self.output_format.write(path, &result)))
+            .map_err(|e| in_file(&name, e))
     }
 }
 
-fn open_file(path: PathBuf) -> BufReader<File> {
-    let f = OpenOptions::new().read(true).open(path).unwrap();
-    BufReader::new(f)
+/// Writes `path` through a temporary file renamed over it, so readers
+/// never see a partial file even when two attempts of a task overlap.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    // A leading dot keeps the name from parsing as a task output.
+    let temporary = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    write(&temporary)
+        .and_then(|()| rename(&temporary, path))
+        .inspect_err(|_| {
+            let _ = remove_file(&temporary);
+        })
+}
+
+/// Why a map or reduce function gave up on its task.
+#[derive(Debug)]
+pub struct TaskError(pub String);
+
+/// Fails the running task with `error`, for map and reduce functions, which
+/// return plain values. The worker reports `error` as the task's failure.
+/// Unlike a panic it is an expected outcome, so nothing is printed.
+pub fn fail_task(error: impl fmt::Display) -> ! {
+    resume_unwind(Box::new(TaskError(error.to_string())))
+}
+
+pub(crate) fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    panic
+        .downcast_ref::<TaskError>()
+        .map(|error| error.0.clone())
+        .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or("task panicked".to_string())
+}
+
+/// Names the file an error is about.
+fn in_file(path: &Path, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::remove_file, io::BufRead, thread};
+    use std::{fs::remove_file, io::BufRead, thread, time::Duration};
 
     use super::*;
+    use crate::pipeline::remove_job_outputs;
 
     fn map_fn(_input: BufReader<File>) -> Vec<String> {
-        vec!["1", "2", "3", "4"]
-            .iter()
-            .map(|s| s.to_string())
-            .collect()
+        ["1", "2", "3", "4"].iter().map(|s| s.to_string()).collect()
     }
 
     fn reduce_fn(_input: Vec<BufReader<File>>) -> String {
         "1234".to_string()
     }
 
+    #[test]
+    fn worker_fails_task_on_permanent_error() {
+        let worker = Worker {
+            working_directory: PathBuf::from("./test-data"),
+            map: Arc::new(|_| vec![]),
+            input_maps: Arc::new(HashMap::new()),
+            reduce: Arc::new(|_| String::new()),
+            status: JobStatus::default(),
+            output_format: OutputFormat::Text,
+            execution: Execution::Thread,
+            shuffle: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            tracer: None,
+            retry: RetryPolicy::default(),
+        };
+
+        let missing = PathBuf::from("./test-data/no_such_input");
+        match worker.process(0, Job::Map((1, missing))) {
+            JobResult::MapFailed(1, error) => assert!(error.contains("no_such_input"), "{}", error),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn worker_retries_transient_errors() {
+        let working_directory = PathBuf::from("./test-data/worker_retries_transient_errors");
+        let input = working_directory.join("input_1");
+        let shuffle = MemoryShuffle::default();
+        let worker = Worker {
+            working_directory: working_directory.clone(),
+            map: Arc::new(map_fn),
+            input_maps: Arc::new(HashMap::new()),
+            reduce: Arc::new(reduce_fn),
+            status: JobStatus::default(),
+            output_format: OutputFormat::Text,
+            execution: Execution::Thread,
+            shuffle: Some(shuffle.clone()),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            tracer: None,
+            retry: RetryPolicy {
+                max_retries: 2,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            },
+        };
+
+        shuffle.fail_next(2, libc::EMFILE);
+        assert_eq!(
+            worker.process(0, Job::Map((1, input.clone()))),
+            JobResult::MapFinished(1)
+        );
+        shuffle.fail_next(3, libc::EMFILE);
+        match worker.process(0, Job::Map((1, input))) {
+            JobResult::MapFailed(1, error) => assert!(error.contains("Too many open files")),
+            result => panic!("{:?}", result),
+        }
+
+        shuffle.fail_next(2, libc::ENOSPC);
+        assert_eq!(
+            worker.process(0, Job::Reduce((1, vec![]))),
+            JobResult::ReduceFinished(1)
+        );
+        shuffle.fail_next(3, libc::ENOSPC);
+        match worker.process(0, Job::Reduce((1, vec![]))) {
+            JobResult::ReduceFailed(1, error) => assert!(error.contains("No space left")),
+            result => panic!("{:?}", result),
+        }
+
+        remove_job_outputs(&working_directory).unwrap();
+    }
+
     #[test]
     fn worker_maps_input_to_result_files() {
         let working_directry = PathBuf::from("./test-data/worker_maps_input_to_result_files");
@@ -119,12 +390,18 @@ mod tests {
         let worker = Worker {
             working_directory: working_directry.clone(),
             map: Arc::new(map_fn),
+            input_maps: Arc::new(HashMap::new()),
             reduce: Arc::new(reduce_fn),
-            job_queue: work_recv,
-            results_queue: results_send,
+            status: JobStatus::default(),
+            output_format: OutputFormat::Text,
+            execution: Execution::Thread,
+            shuffle: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            tracer: None,
+            retry: RetryPolicy::default(),
         };
 
-        thread::spawn(move || worker.run());
+        thread::spawn(move || worker.run(0, work_recv, results_send));
 
         work_send.send(Job::Map((1, map_file.clone())));
         let done = results_recv.recv();
@@ -181,12 +458,18 @@ mod tests {
         let worker = Worker {
             working_directory: working_directory.clone(),
             map: Arc::new(map_fn),
+            input_maps: Arc::new(HashMap::new()),
             reduce: Arc::new(reduce_fn),
-            job_queue: work_recv,
-            results_queue: results_send,
+            status: JobStatus::default(),
+            output_format: OutputFormat::Text,
+            execution: Execution::Thread,
+            shuffle: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            tracer: None,
+            retry: RetryPolicy::default(),
         };
 
-        thread::spawn(move || worker.run());
+        thread::spawn(move || worker.run(0, work_recv, results_send));
 
         work_send.send(Job::Reduce((2, reduce_files)));
         let done = results_recv.recv();
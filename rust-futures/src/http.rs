@@ -61,7 +61,7 @@ impl Future for HttpGetFuture {
         if self.stream.is_none() {
             println!("FIRST POLL - START OPERATION");
             self.write_request();
-            let stream = (&mut self).stream.as_mut().unwrap();
+            let stream = self.stream.as_mut().unwrap();
             runtime::reactor::reactor()
                 .register(stream, Interest::READABLE, id)
                 .expect("register");
@@ -31,6 +31,7 @@ where
     });
 }
 
+#[derive(Default)]
 pub struct Executor;
 
 impl Executor {
@@ -83,14 +84,9 @@ impl Executor {
                 }
             }
 
-            let task_count = self.task_count();
-            let name = thread::current().name().unwrap_or_default().to_string();
-
-            if task_count > 0 {
-                println!("{name}: {task_count} pending tasks. Sleep until notified.");
+            if self.task_count() > 0 {
                 std::thread::park();
             } else {
-                println!("{name}: All tasks are finished");
                 break;
             }
         }
@@ -8,7 +8,7 @@ use std::{
     task::{Context, Waker},
 };
 
-use mio::{net::TcpStream, Events, Interest, Poll, Registry, Token};
+use mio::{event::Source, Events, Interest, Poll, Registry, Token};
 
 type Wakers = Arc<Mutex<HashMap<usize, Waker>>>;
 
@@ -35,13 +35,13 @@ impl Reactor {
         }
     }
 
-    pub fn register(
+    pub fn register<S: Source + ?Sized>(
         &self,
-        stream: &mut TcpStream,
+        source: &mut S,
         interest: Interest,
         id: usize,
     ) -> io::Result<()> {
-        self.registry.register(stream, Token(id), interest)
+        self.registry.register(source, Token(id), interest)
     }
 
     pub fn set_waker(&self, cx: &Context, id: usize) {
@@ -53,9 +53,9 @@ impl Reactor {
             .unwrap();
     }
 
-    pub fn deregister(&self, stream: &mut TcpStream, id: usize) -> io::Result<()> {
+    pub fn deregister<S: Source + ?Sized>(&self, source: &mut S, id: usize) -> io::Result<()> {
         self.wakers.lock().map(|mut w| w.remove(&id)).unwrap();
-        self.registry.deregister(stream)
+        self.registry.deregister(source)
     }
 
     pub fn next_id(&self) -> usize {